@@ -0,0 +1,112 @@
+//! Checking a destination against a previously written manifest, without the original source.
+
+use std::{
+    collections::HashSet,
+    fmt::{self, Display},
+    fs, io,
+    path::Path,
+};
+
+use crate::{
+    error::{Error, Result},
+    hash::Digest,
+    journal::JOURNAL_DIR,
+    manifest, walk, AuditOpts,
+};
+
+#[derive(Clone, Debug, Default)]
+struct Report {
+    intact: u64,
+    corrupted: u64,
+    missing: u64,
+    unexpected: u64,
+    unreadable: u64,
+}
+
+impl Report {
+    fn problems(&self) -> u64 {
+        self.corrupted + self.missing + self.unexpected + self.unreadable
+    }
+
+    fn corrupted(&mut self, path: &Path) {
+        self.corrupted += 1;
+        println!("corrupted {}", path.display());
+    }
+
+    fn missing(&mut self, path: &Path) {
+        self.missing += 1;
+        println!("missing {}", path.display());
+    }
+
+    fn unexpected(&mut self, path: &Path) {
+        self.unexpected += 1;
+        println!("unexpected {}", path.display());
+    }
+
+    fn unreadable(&mut self, error: Error) {
+        self.unreadable += 1;
+        println!("unreadable {}", error);
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} intact, {} corrupted, {} missing, {} unexpected, {} unreadable",
+            self.intact, self.corrupted, self.missing, self.unexpected, self.unreadable
+        )
+    }
+}
+
+pub fn audit(opts: &AuditOpts) -> Result<()> {
+    let entries = manifest::read(&opts.manifest).map_err(Error::manifest(&opts.manifest))?;
+    let mut report = Report::default();
+    let filter = opts.filter();
+
+    for (path, expected) in &entries {
+        let absolute_path = opts.root.join(path);
+        match Digest::from_path(&absolute_path, expected.algorithm()) {
+            Ok(digest) if digest == *expected => report.intact += 1,
+            Ok(_) => report.corrupted(path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => report.missing(path),
+            Err(e) => report.unreadable(Error::hash_destination(&absolute_path)(e)),
+        }
+    }
+
+    // The manifest itself may well be stored alongside the files it describes.
+    let manifest_path =
+        fs::canonicalize(&opts.manifest).map_err(Error::manifest(&opts.manifest))?;
+    let own_path = fs::canonicalize(&opts.root)
+        .ok()
+        .and_then(|root| manifest_path.strip_prefix(root).ok().map(Path::to_owned));
+
+    let listed: HashSet<_> = entries.iter().map(|(path, _)| path.as_path()).collect();
+    for entry in walk(&opts.root, &filter, false) {
+        let object = match entry {
+            Ok(object) => object,
+            Err(e) => {
+                report.unreadable(e);
+                continue;
+            }
+        };
+
+        if !object.file_type.is_file()
+            || object.relative_path.starts_with(JOURNAL_DIR)
+            || own_path.as_deref() == Some(object.relative_path.as_path())
+        {
+            continue;
+        }
+
+        if !listed.contains(object.relative_path.as_path()) {
+            report.unexpected(&object.relative_path);
+        }
+    }
+
+    println!("{}", report);
+
+    match report.problems() {
+        0 => Ok(()),
+        n => Err(Error::Differences(n)),
+    }
+}
@@ -0,0 +1,156 @@
+//! Keeping the previous contents of a destination file before it is overwritten.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::symlink;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Backup {
+    /// Rename to `name.~n~`, using the first free `n`.
+    Numbered,
+    /// Rename to the file name plus this suffix.
+    Suffix(String),
+    /// Move into this directory, mirroring the layout of the destination.
+    Dir(PathBuf),
+}
+
+impl FromStr for Backup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            Err(String::from("backup suffix must not be empty"))
+        } else if s.contains(std::path::is_separator) {
+            Ok(Backup::Dir(PathBuf::from(s)))
+        } else {
+            Ok(Backup::Suffix(s.to_owned()))
+        }
+    }
+}
+
+impl Backup {
+    /// Moves `destination`, which lies beneath `root`, out of the way and returns its new path.
+    pub fn back_up(&self, root: &Path, destination: &Path) -> io::Result<PathBuf> {
+        let target = match self {
+            Backup::Numbered => numbered(destination),
+            Backup::Suffix(suffix) => {
+                let mut name = destination.file_name().unwrap_or_default().to_owned();
+                name.push(suffix);
+                destination.with_file_name(name)
+            }
+            Backup::Dir(dir) => {
+                let relative = destination.strip_prefix(root).unwrap_or(destination);
+                let target = dir.join(relative);
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+
+                // Never overwrite an older backup; number this one instead.
+                if target.exists() {
+                    numbered(&target)
+                } else {
+                    target
+                }
+            }
+        };
+
+        move_file(destination, &target)?;
+        Ok(target)
+    }
+}
+
+fn numbered(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    (1..)
+        .map(|n| path.with_file_name(format!("{}.~{}~", name, n)))
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
+
+/// Renames `from` to `to`, falling back to copying and deleting when they are on different
+/// filesystems. A symlink is moved as a link, never by copying what it points to.
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if let Err(e) = fs::rename(from, to) {
+        let copied = match fs::read_link(from) {
+            Ok(target) => symlink::create(&target, to),
+            Err(_) => fs::copy(from, to).map(drop),
+        };
+        if copied.is_err() {
+            return Err(e);
+        }
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scratch;
+
+    #[test]
+    fn numbered_backups_take_the_first_free_number() {
+        let scratch = Scratch::new("backup-numbered");
+        let destination = scratch.join("file.txt");
+        for contents in ["first", "second"] {
+            fs::write(&destination, contents).unwrap();
+            Backup::Numbered
+                .back_up(scratch.path(), &destination)
+                .unwrap();
+        }
+
+        assert!(!destination.exists());
+        assert_eq!(fs::read(scratch.join("file.txt.~1~")).unwrap(), b"first");
+        assert_eq!(fs::read(scratch.join("file.txt.~2~")).unwrap(), b"second");
+    }
+
+    #[test]
+    fn suffix_is_appended_to_the_name() {
+        let scratch = Scratch::new("backup-suffix");
+        let destination = scratch.join("file.txt");
+        fs::write(&destination, "old").unwrap();
+
+        let backup: Backup = "~".parse().unwrap();
+        let target = backup.back_up(scratch.path(), &destination).unwrap();
+        assert_eq!(target, scratch.join("file.txt~"));
+        assert_eq!(fs::read(target).unwrap(), b"old");
+    }
+
+    #[test]
+    fn backup_dir_mirrors_the_destination() {
+        let scratch = Scratch::new("backup-dir");
+        let root = scratch.join("root");
+        let destination = root.join("a/b/file.txt");
+        fs::create_dir_all(destination.parent().unwrap()).unwrap();
+
+        let backup = Backup::Dir(scratch.join("backups"));
+        let mut targets = Vec::new();
+        for contents in ["first", "second"] {
+            fs::write(&destination, contents).unwrap();
+            targets.push(backup.back_up(&root, &destination).unwrap());
+        }
+
+        let mirrored = scratch.join("backups/a/b/file.txt");
+        assert_eq!(targets[0], mirrored);
+        assert_eq!(fs::read(&mirrored).unwrap(), b"first");
+        assert_eq!(targets[1], scratch.join("backups/a/b/file.txt.~1~"));
+        assert_eq!(fs::read(&targets[1]).unwrap(), b"second");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_backed_up_as_links() {
+        let scratch = Scratch::new("backup-symlink");
+        let destination = scratch.join("link");
+        symlink::create(Path::new("nowhere"), &destination).unwrap();
+
+        let target = Backup::Numbered
+            .back_up(scratch.path(), &destination)
+            .unwrap();
+        assert_eq!(fs::read_link(target).unwrap(), Path::new("nowhere"));
+    }
+}
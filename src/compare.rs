@@ -0,0 +1,157 @@
+//! Comparing files already at the destination with their sources: by sampled imprint unless a
+//! digest algorithm is asked for, and byte for byte when neither is enough.
+
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+use imprint::Imprint;
+
+use crate::{
+    durable::{self, Uncached},
+    error::{Error, Result},
+    hash::{Algorithm, Digest, Hasher},
+};
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// How a file already in place is compared with its source.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Comparison {
+    /// Compare by digests made with this algorithm rather than by imprint.
+    pub algorithm: Option<Algorithm>,
+    /// Compare byte for byte rather than by imprint or digest.
+    pub full: bool,
+    /// Read the destination from the device rather than the page cache.
+    pub uncached: Option<Uncached>,
+}
+
+/// How a file was found to hold the same contents as its source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Match {
+    /// By sampled imprint, which says nothing about the rest of the contents.
+    Imprint,
+    /// By reading all of the contents, whose digest this is.
+    Digest(Digest),
+}
+
+impl Match {
+    pub fn digest(self) -> Option<Digest> {
+        match self {
+            Match::Imprint => None,
+            Match::Digest(digest) => Some(digest),
+        }
+    }
+}
+
+impl Comparison {
+    /// Whether `destination` holds the same contents as `source`, and how that was established.
+    pub fn matches(&self, source: &Path, destination: &Path) -> Result<Option<Match>> {
+        if self.algorithm.is_none() || self.full {
+            // Imprints and byte comparisons read by path, so the best that can be done is to
+            // empty the cache first.
+            if self.uncached.is_some()
+                && !durable::evict(destination).map_err(Error::hash_destination(destination))?
+            {
+                durable::note_cached_reads();
+            }
+        }
+
+        if self.full {
+            let mut hasher = self.digest_algorithm().hasher();
+            let same = compare(source, destination, Some(&mut *hasher))?;
+            return Ok(same.then(|| Match::Digest(hasher.finish())));
+        }
+
+        let algorithm = match self.algorithm {
+            Some(algorithm) => algorithm,
+            None => {
+                let source_imprint = Imprint::new(source).map_err(Error::hash_source(source))?;
+                let destination_imprint =
+                    Imprint::new(destination).map_err(Error::hash_destination(destination))?;
+                return Ok((source_imprint == destination_imprint).then_some(Match::Imprint));
+            }
+        };
+
+        let source_digest =
+            Digest::from_path(source, algorithm).map_err(Error::hash_source(source))?;
+        let destination_digest = self.digest(destination)?;
+        Ok((source_digest == destination_digest).then_some(Match::Digest(source_digest)))
+    }
+
+    /// Computes the digest of a file at the destination, from the device itself if asked to.
+    pub fn digest(&self, path: &Path) -> Result<Digest> {
+        let algorithm = self.digest_algorithm();
+        match self.uncached {
+            Some(mode) => durable::digest_uncached(path, mode, algorithm),
+            None => Digest::from_path(path, algorithm),
+        }
+        .map_err(Error::hash_destination(path))
+    }
+
+    /// The algorithm for digests, which are still needed for copies and manifests when files are
+    /// compared by imprint.
+    fn digest_algorithm(&self) -> Algorithm {
+        self.algorithm.unwrap_or_default()
+    }
+}
+
+/// Compares the complete contents of `source` and `destination`.
+pub fn files_match(source: &Path, destination: &Path) -> Result<bool> {
+    compare(source, destination, None)
+}
+
+/// Compares `source` and `destination` byte for byte, feeding the source's bytes to `hasher` on
+/// the way.
+fn compare(source: &Path, destination: &Path, mut hasher: Option<&mut dyn Hasher>) -> Result<bool> {
+    let mut source_file = File::open(source).map_err(Error::hash_source(source))?;
+    let mut destination_file =
+        File::open(destination).map_err(Error::hash_destination(destination))?;
+
+    let source_len = source_file
+        .metadata()
+        .map_err(Error::hash_source(source))?
+        .len();
+    let destination_len = destination_file
+        .metadata()
+        .map_err(Error::hash_destination(destination))?
+        .len();
+    if source_len != destination_len {
+        return Ok(false);
+    }
+
+    let mut source_buf = vec![0; BUFFER_SIZE];
+    let mut destination_buf = vec![0; BUFFER_SIZE];
+    loop {
+        let len = fill(&mut source_file, &mut source_buf).map_err(Error::hash_source(source))?;
+        let destination_len = fill(&mut destination_file, &mut destination_buf)
+            .map_err(Error::hash_destination(destination))?;
+
+        if source_buf[..len] != destination_buf[..destination_len] {
+            return Ok(false);
+        }
+        if len == 0 {
+            return Ok(true);
+        }
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.update(&source_buf[..len]);
+        }
+    }
+}
+
+/// Reads until `buf` is full or the end of the file is reached, so that two files can be compared
+/// a buffer at a time however their reads happen to be split.
+fn fill(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(len) => filled += len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
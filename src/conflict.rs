@@ -0,0 +1,202 @@
+//! What to do when a file already exists at the destination with different contents.
+
+use std::{
+    fs::{self, Metadata},
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Mutex, PoisonError},
+};
+
+use crate::{
+    error::{Error, Result},
+    special,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// Replace the destination with the source.
+    Overwrite,
+    /// Leave the destination alone.
+    Skip,
+    /// Keep both, copying the source under a new name such as `name (1).ext`.
+    Rename,
+    /// Replace the destination only if the source was modified more recently.
+    Newer,
+    /// Replace the destination only if the source is larger.
+    Larger,
+    /// Stop with an error.
+    Fail,
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "overwrite" => Ok(Policy::Overwrite),
+            "skip" => Ok(Policy::Skip),
+            "rename" => Ok(Policy::Rename),
+            "newer" => Ok(Policy::Newer),
+            "larger" => Ok(Policy::Larger),
+            "fail" => Ok(Policy::Fail),
+            _ => Err(format!("unknown conflict policy: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Resolution {
+    Overwrite,
+    Keep(&'static str),
+    Rename(PathBuf),
+    /// An earlier rename already holds a copy of the source.
+    Existing(PathBuf),
+}
+
+impl Resolution {
+    pub fn describe(&self, dry_run: bool) -> String {
+        match (self, dry_run) {
+            (Resolution::Overwrite, false) => String::from("overwriting destination"),
+            (Resolution::Overwrite, true) => String::from("would overwrite destination"),
+            (Resolution::Keep(reason), false) => format!("keeping {}", reason),
+            (Resolution::Keep(reason), true) => format!("would keep {}", reason),
+            (Resolution::Rename(path), false) => format!("renaming to {}", file_name(path)),
+            (Resolution::Rename(path), true) => format!("would rename to {}", file_name(path)),
+            (Resolution::Existing(path), _) => format!("already copied as {}", file_name(path)),
+        }
+    }
+}
+
+/// Decides what to do about `destination`, which exists and differs from `source`. Names already
+/// taken by earlier renames are checked for a copy of the source with `matches`.
+pub fn resolve(
+    policy: Policy,
+    source: &Path,
+    destination: &Path,
+    matches: impl FnMut(&Path) -> Result<bool>,
+) -> Result<Resolution> {
+    decide(
+        policy,
+        source,
+        destination,
+        |path| fs::metadata(path),
+        matches,
+    )
+}
+
+/// Decides what to do about `destination` when the symlink `source`, pointing at `target`, is to
+/// be copied there. The links themselves are compared rather than what they point to, and a
+/// name taken by an earlier rename matches if it is a link to the same target.
+pub fn resolve_link(
+    policy: Policy,
+    source: &Path,
+    destination: &Path,
+    target: &Path,
+) -> Result<Resolution> {
+    decide(
+        policy,
+        source,
+        destination,
+        |path| fs::symlink_metadata(path),
+        |candidate| Ok(fs::read_link(candidate).is_ok_and(|existing| existing == target)),
+    )
+}
+
+/// Decides what to do about `destination` when the special file `source` is to be recreated
+/// there. A name taken by an earlier rename matches if it is a node of the same kind and device.
+pub fn resolve_special(policy: Policy, source: &Path, destination: &Path) -> Result<Resolution> {
+    decide(
+        policy,
+        source,
+        destination,
+        |path| fs::symlink_metadata(path),
+        |candidate| Ok(special::same_node(source, candidate).unwrap_or(false)),
+    )
+}
+
+fn decide(
+    policy: Policy,
+    source: &Path,
+    destination: &Path,
+    metadata: fn(&Path) -> io::Result<Metadata>,
+    matches: impl FnMut(&Path) -> Result<bool>,
+) -> Result<Resolution> {
+    let modified = |path: &Path| metadata(path)?.modified();
+    match policy {
+        Policy::Overwrite => Ok(Resolution::Overwrite),
+        Policy::Skip => Ok(Resolution::Keep("destination")),
+        Policy::Rename => rename(destination, matches),
+        Policy::Newer => {
+            let source_modified = modified(source).map_err(Error::metadata(source))?;
+            let destination_modified =
+                modified(destination).map_err(Error::metadata(destination))?;
+            if source_modified > destination_modified {
+                Ok(Resolution::Overwrite)
+            } else {
+                Ok(Resolution::Keep("newer destination"))
+            }
+        }
+        Policy::Larger => {
+            let source_len = metadata(source).map_err(Error::metadata(source))?.len();
+            let destination_len = metadata(destination)
+                .map_err(Error::metadata(destination))?
+                .len();
+            if source_len > destination_len {
+                Ok(Resolution::Overwrite)
+            } else {
+                Ok(Resolution::Keep("larger destination"))
+            }
+        }
+        Policy::Fail => Err(Error::Conflict(destination.to_owned())),
+    }
+}
+
+/// Names handed out by `rename` during this run.
+static RESERVED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Finds the first free name of the form `name (n).ext`, unless one of the names already taken
+/// holds a copy of the source.
+fn rename(
+    destination: &Path,
+    mut matches: impl FnMut(&Path) -> Result<bool>,
+) -> Result<Resolution> {
+    let stem = destination
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = destination
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+
+    for n in 1.. {
+        let candidate = destination.with_file_name(format!("{} ({}){}", stem, n, extension));
+        {
+            // Workers renaming at once would otherwise all settle on the same free name. A
+            // reserved name has yet to be written, so there is nothing there to match either.
+            let mut reserved = RESERVED.lock().unwrap_or_else(PoisonError::into_inner);
+            if reserved.contains(&candidate) {
+                continue;
+            }
+
+            // A link pointing nowhere still takes up the name.
+            if fs::symlink_metadata(&candidate).is_err() {
+                reserved.push(candidate.clone());
+                return Ok(Resolution::Rename(candidate));
+            }
+        }
+
+        if matches(&candidate)? {
+            return Ok(Resolution::Existing(candidate));
+        }
+    }
+
+    unreachable!()
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
@@ -0,0 +1,169 @@
+//! Getting copies onto the medium, and reading them back from it, before trusting them.
+
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+    str::FromStr,
+    sync::Once,
+};
+
+use crate::hash::{Algorithm, Digest};
+
+static UNCACHED_UNSUPPORTED: Once = Once::new();
+static DIRECT_UNSUPPORTED: Once = Once::new();
+
+/// Alignment required of buffers, offsets and lengths for direct reads.
+const DIRECT_ALIGN: usize = 4096;
+const DIRECT_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// How to get past the page cache when reading a file back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Uncached {
+    /// Flush the file and ask the kernel to drop its cached pages before reading it.
+    Evict,
+    /// Read with `O_DIRECT`, falling back to eviction where the filesystem refuses it.
+    Direct,
+}
+
+impl FromStr for Uncached {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "evict" => Ok(Uncached::Evict),
+            "direct" => Ok(Uncached::Direct),
+            _ => Err(format!("unknown uncached read mode: {}", s)),
+        }
+    }
+}
+
+/// Computes the digest of the file at `path` as stored on the device rather than as cached.
+pub fn digest_uncached(path: &Path, mode: Uncached, algorithm: Algorithm) -> io::Result<Digest> {
+    if mode == Uncached::Direct {
+        match open_direct(path)
+            .and_then(|file| Digest::from_reader(DirectReader::new(file), algorithm))
+        {
+            Err(e) if is_direct_unsupported(&e) => DIRECT_UNSUPPORTED.call_once(|| {
+                eprintln!(
+                    "note: direct reads are not supported for {}; evicting cached pages instead",
+                    path.display()
+                );
+            }),
+            result => return result,
+        }
+    }
+
+    if !evict(path)? {
+        note_cached_reads();
+    }
+    Digest::from_path(path, algorithm)
+}
+
+/// Flushes a directory, so that the entries created or renamed within it survive a power cut.
+#[cfg(unix)]
+pub fn sync_dir(path: &Path) -> io::Result<()> {
+    File::open(path)?.sync_all()
+}
+
+/// Directories cannot be opened for flushing here; their entries are made durable along with
+/// the files themselves.
+#[cfg(not(unix))]
+pub fn sync_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Drops any cached pages of the file at `path`, so that the next read comes from the device.
+/// Returns false where this is not supported.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn evict(path: &Path) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    // Only clean pages can be dropped.
+    let file = File::open(path)?;
+    file.sync_data()?;
+
+    let ret = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+    if ret != 0 {
+        return Err(io::Error::from_raw_os_error(ret));
+    }
+    Ok(true)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn evict(_path: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
+/// Says, once per run, that reads could not be forced past the page cache.
+pub fn note_cached_reads() {
+    UNCACHED_UNSUPPORTED.call_once(|| {
+        eprintln!(
+            "note: this platform cannot bypass the page cache, so read-back verification may \
+             see cached data rather than the device"
+        );
+    });
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn open_direct(path: &Path) -> io::Result<File> {
+    use std::{fs::OpenOptions, os::unix::fs::OpenOptionsExt};
+
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(path)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn open_direct(_path: &Path) -> io::Result<File> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Filesystems without direct I/O refuse it with `EINVAL`, either on open or on the first read.
+fn is_direct_unsupported(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    if e.raw_os_error() == Some(libc::EINVAL) {
+        return true;
+    }
+    e.kind() == io::ErrorKind::Unsupported
+}
+
+/// Reads a file opened for direct I/O through a suitably aligned buffer.
+struct DirectReader {
+    file: File,
+    buf: Vec<u8>,
+    offset: usize,
+    pos: usize,
+    len: usize,
+}
+
+impl DirectReader {
+    fn new(file: File) -> Self {
+        let buf = vec![0; DIRECT_BUFFER_SIZE + DIRECT_ALIGN];
+        let offset = buf.as_ptr().align_offset(DIRECT_ALIGN);
+        DirectReader {
+            file,
+            buf,
+            offset,
+            pos: 0,
+            len: 0,
+        }
+    }
+}
+
+impl Read for DirectReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.len {
+            let aligned = &mut self.buf[self.offset..self.offset + DIRECT_BUFFER_SIZE];
+            self.len = self.file.read(aligned)?;
+            self.pos = 0;
+        }
+
+        let available = &self.buf[self.offset + self.pos..self.offset + self.len];
+        let len = available.len().min(out.len());
+        out[..len].copy_from_slice(&available[..len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
@@ -0,0 +1,410 @@
+use std::{
+    fmt::{self, Display},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Clone, Debug)]
+pub struct BadCopy {
+    source: PathBuf,
+    destination: PathBuf,
+}
+
+impl BadCopy {
+    pub fn new(source: impl Into<PathBuf>, destination: impl Into<PathBuf>) -> Self {
+        Self {
+            source: source.into(),
+            destination: destination.into(),
+        }
+    }
+}
+
+impl Display for BadCopy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "bad copy:\n  source: {}\n  destination: {}",
+            self.source.display(),
+            self.destination.display()
+        )
+    }
+}
+
+impl std::error::Error for BadCopy {}
+
+/// Everything that can go wrong, each tagged with the operation that failed and the paths
+/// involved.
+#[derive(Debug)]
+pub enum Error {
+    BadCopy(BadCopy),
+    HashSource {
+        path: PathBuf,
+        source: io::Error,
+    },
+    HashDestination {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Copy {
+        from: PathBuf,
+        to: PathBuf,
+        source: io::Error,
+    },
+    Mkdir {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Remove {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Journal {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Manifest {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Metadata {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Backup {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Preserve {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// A special file was met under a policy of failing on them.
+    Special {
+        path: PathBuf,
+        kind: &'static str,
+    },
+    /// Some extended attributes of a copy could not be set, in strict mode.
+    Attributes {
+        path: PathBuf,
+        unset: usize,
+    },
+    Walk {
+        path: PathBuf,
+        source: walkdir::Error,
+    },
+    Pattern {
+        pattern: String,
+        source: globset::Error,
+    },
+    PatternFile {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// A destination differed from its source under `--on-conflict fail`.
+    Conflict(PathBuf),
+    /// A `verify` or `audit` found differences.
+    Differences(u64),
+    /// A run with `--keep-going` finished, but not every entry made it.
+    Failures(usize),
+}
+
+impl Error {
+    pub fn hash_source(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Error::HashSource {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    pub fn hash_destination(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Error::HashDestination {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    pub fn copy<'a>(from: &'a Path, to: &'a Path) -> impl FnOnce(io::Error) -> Self + 'a {
+        move |source| Error::Copy {
+            from: from.to_owned(),
+            to: to.to_owned(),
+            source,
+        }
+    }
+
+    pub fn mkdir(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Error::Mkdir {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    pub fn remove(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Error::Remove {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    pub fn journal(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Error::Journal {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    pub fn manifest(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Error::Manifest {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    pub fn metadata(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Error::Metadata {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    pub fn backup(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Error::Backup {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    pub fn preserve(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Error::Preserve {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    pub fn walk(root: &Path) -> impl FnOnce(walkdir::Error) -> Self + '_ {
+        move |source| Error::Walk {
+            path: source.path().unwrap_or(root).to_owned(),
+            source,
+        }
+    }
+
+    pub fn pattern(pattern: &str) -> impl FnOnce(globset::Error) -> Self + '_ {
+        move |source| Error::Pattern {
+            pattern: pattern.to_owned(),
+            source,
+        }
+    }
+
+    pub fn pattern_file(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Error::PatternFile {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    /// The path at which the walk failed, when this is a walk error.
+    pub fn walk_path(&self) -> Option<&Path> {
+        match self {
+            Error::Walk { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Whether this is a walk error from following a link that loops back on itself or leads
+    /// nowhere.
+    pub fn is_broken_link(&self) -> bool {
+        match self {
+            Error::Walk { path, source } => {
+                source.loop_ancestor().is_some()
+                    || (source
+                        .io_error()
+                        .is_some_and(|e| e.kind() == io::ErrorKind::NotFound)
+                        && fs::symlink_metadata(path)
+                            .is_ok_and(|metadata| metadata.file_type().is_symlink()))
+            }
+            _ => false,
+        }
+    }
+
+    /// A short name for the operation that failed.
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Error::BadCopy(_) => "bad copy",
+            Error::HashSource { .. } => "hash source",
+            Error::HashDestination { .. } => "hash destination",
+            Error::Copy { .. } => "copy",
+            Error::Mkdir { .. } => "mkdir",
+            Error::Remove { .. } => "remove",
+            Error::Journal { .. } => "journal",
+            Error::Manifest { .. } => "manifest",
+            Error::Metadata { .. } => "metadata",
+            Error::Backup { .. } => "backup",
+            Error::Preserve { .. } => "preserve",
+            Error::Attributes { .. } => "attributes",
+            Error::Special { .. } => "special file",
+            Error::Walk { .. } => "walk",
+            Error::Pattern { .. } => "pattern",
+            Error::PatternFile { .. } => "pattern file",
+            Error::Conflict(_) => "conflict",
+            Error::Differences(_) => "differences",
+            Error::Failures(_) => "failures",
+        }
+    }
+
+    /// The process exit code for this error. Each variant has its own code so that scripts can
+    /// tell, for instance, a failed verification from a permission problem.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Failures(_) => 2,
+            Error::BadCopy(_) => 3,
+            Error::Differences(_) => 4,
+            Error::HashSource { .. } => 5,
+            Error::HashDestination { .. } => 6,
+            Error::Copy { .. } => 7,
+            Error::Mkdir { .. } => 8,
+            Error::Remove { .. } => 9,
+            Error::Journal { .. } => 10,
+            Error::Manifest { .. } => 11,
+            Error::Walk { .. } => 12,
+            Error::Pattern { .. } => 13,
+            Error::PatternFile { .. } => 14,
+            Error::Conflict(_) => 15,
+            Error::Metadata { .. } => 16,
+            Error::Backup { .. } => 17,
+            Error::Preserve { .. } => 18,
+            Error::Attributes { .. } => 19,
+            Error::Special { .. } => 20,
+        }
+    }
+
+    /// The underlying I/O error, if any.
+    pub fn io_error(&self) -> Option<&io::Error> {
+        match self {
+            Error::HashSource { source, .. }
+            | Error::HashDestination { source, .. }
+            | Error::Copy { source, .. }
+            | Error::Mkdir { source, .. }
+            | Error::Remove { source, .. }
+            | Error::Journal { source, .. }
+            | Error::Manifest { source, .. }
+            | Error::Metadata { source, .. }
+            | Error::Backup { source, .. }
+            | Error::Preserve { source, .. }
+            | Error::PatternFile { source, .. } => Some(source),
+            Error::Walk { source, .. } => source.io_error(),
+            Error::BadCopy(_)
+            | Error::Pattern { .. }
+            | Error::Conflict(_)
+            | Error::Attributes { .. }
+            | Error::Special { .. }
+            | Error::Differences(_)
+            | Error::Failures(_) => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BadCopy(e) => e.fmt(f),
+            Error::HashSource { path, source } => {
+                write!(f, "unable to hash source {}: {}", path.display(), source)
+            }
+            Error::HashDestination { path, source } => {
+                write!(
+                    f,
+                    "unable to hash destination {}: {}",
+                    path.display(),
+                    source
+                )
+            }
+            Error::Copy { from, to, source } => write!(
+                f,
+                "unable to copy {} to {}: {}",
+                from.display(),
+                to.display(),
+                source
+            ),
+            Error::Mkdir { path, source } => {
+                write!(f, "unable to create {}: {}", path.display(), source)
+            }
+            Error::Remove { path, source } => {
+                write!(f, "unable to remove {}: {}", path.display(), source)
+            }
+            Error::Journal { path, source } => {
+                write!(f, "journal error in {}: {}", path.display(), source)
+            }
+            Error::Manifest { path, source } => {
+                write!(f, "manifest error in {}: {}", path.display(), source)
+            }
+            Error::Metadata { path, source } => {
+                write!(
+                    f,
+                    "unable to read metadata for {}: {}",
+                    path.display(),
+                    source
+                )
+            }
+            Error::Backup { path, source } => {
+                write!(f, "unable to back up {}: {}", path.display(), source)
+            }
+            Error::Preserve { path, source } => {
+                write!(
+                    f,
+                    "unable to preserve attributes of {}: {}",
+                    path.display(),
+                    source
+                )
+            }
+            Error::Attributes { path, unset } => {
+                write!(
+                    f,
+                    "unable to set {} attributes on {}",
+                    unset,
+                    path.display()
+                )
+            }
+            Error::Special { path, kind } => {
+                write!(f, "refusing to copy {} {}", kind, path.display())
+            }
+            Error::Walk { path, source } => match source.io_error() {
+                Some(e) => write!(f, "unable to read {}: {}", path.display(), e),
+                None => write!(f, "unable to read {}: {}", path.display(), source),
+            },
+            Error::Pattern { pattern, source } => {
+                write!(f, "invalid pattern {}: {}", pattern, source)
+            }
+            Error::PatternFile { path, source } => {
+                write!(
+                    f,
+                    "unable to read patterns from {}: {}",
+                    path.display(),
+                    source
+                )
+            }
+            Error::Conflict(path) => {
+                write!(f, "destination exists and differs: {}", path.display())
+            }
+            Error::Differences(n) => write!(f, "{} differences found", n),
+            Error::Failures(n) => write!(f, "{} entries failed", n),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::BadCopy(e) => Some(e),
+            Error::Walk { source, .. } => Some(source),
+            Error::Pattern { source, .. } => Some(source),
+            _ => self.io_error().map(|e| e as _),
+        }
+    }
+}
+
+impl From<BadCopy> for Error {
+    fn from(e: BadCopy) -> Self {
+        Error::BadCopy(e)
+    }
+}
@@ -0,0 +1,484 @@
+//! Selection of the entries a walk visits. Rejected directories are pruned along with everything
+//! beneath them, so a skipped directory never leaves orphaned children behind.
+//!
+//! Include and exclude patterns are globs matched against the path of an entry relative to the
+//! root of the walk. A pattern containing no `/` matches a name at any depth; any other pattern
+//! is anchored to the root. A trailing `/` restricts a pattern to directories. Rules apply in
+//! this order, and the first that decides an entry wins:
+//!
+//! 1. The root itself is always visited.
+//! 2. Hidden entries are skipped according to the hidden entry policy.
+//! 3. When ignore files are respected, anything they ignore is skipped.
+//! 4. Anything matching an exclude pattern is skipped.
+//! 5. If include patterns were given, files must match one of them. Directories are not subject
+//!    to include patterns, so that matching files beneath them can still be found.
+//! 6. Everything else is visited.
+//!
+//! Ignore files (`.gitignore`, `.ignore` and `.checkedcopyignore`) follow gitignore semantics
+//! and may appear in any directory of the tree. Rules in deeper directories take precedence, as
+//! do later files in that list over earlier ones in the same directory.
+
+use std::{
+    cell::{Cell, RefCell},
+    fs,
+    path::Path,
+    str::FromStr,
+};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use walkdir::DirEntry;
+
+use crate::error::{Error, Result};
+
+const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore", ".checkedcopyignore"];
+
+/// Which hidden (dot-prefixed) entries to skip.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkipHidden {
+    Nothing,
+    Files,
+    Dirs,
+    All,
+}
+
+impl FromStr for SkipHidden {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "files" => Ok(SkipHidden::Files),
+            "dirs" => Ok(SkipHidden::Dirs),
+            "all" => Ok(SkipHidden::All),
+            _ => Err(format!("unknown hidden entry policy: {}", s)),
+        }
+    }
+}
+
+/// A set of include or exclude globs.
+#[derive(Clone, Debug)]
+pub struct Patterns {
+    any: GlobSet,
+    dirs: GlobSet,
+}
+
+impl Patterns {
+    pub fn new<'a>(patterns: impl IntoIterator<Item = &'a str>) -> Result<Self> {
+        let patterns: Vec<_> = patterns.into_iter().collect();
+        let mut any = GlobSetBuilder::new();
+        let mut dirs = GlobSetBuilder::new();
+
+        for &pattern in &patterns {
+            let (glob, dirs_only) = match pattern.strip_suffix('/') {
+                Some(glob) => (glob, true),
+                None => (pattern, false),
+            };
+
+            let glob = if glob.contains('/') {
+                glob.trim_start_matches('/').to_owned()
+            } else {
+                format!("**/{}", glob)
+            };
+
+            let glob = GlobBuilder::new(&glob)
+                .literal_separator(true)
+                .build()
+                .map_err(Error::pattern(pattern))?;
+
+            if dirs_only {
+                dirs.add(glob);
+            } else {
+                any.add(glob);
+            }
+        }
+
+        let all = patterns.join(", ");
+        Ok(Patterns {
+            any: any.build().map_err(Error::pattern(&all))?,
+            dirs: dirs.build().map_err(Error::pattern(&all))?,
+        })
+    }
+
+    /// Reads patterns from a file, one per line. Blank lines and lines starting with `#` are
+    /// ignored.
+    pub fn from_file(path: &Path) -> Result<Vec<String>> {
+        let text = fs::read_to_string(path).map_err(Error::pattern_file(path))?;
+        Ok(text
+            .lines()
+            .map(str::trim_end)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect())
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        self.any.is_match(path) || is_dir && self.dirs.is_match(path)
+    }
+}
+
+#[derive(Debug)]
+pub struct Filter {
+    hidden: SkipHidden,
+    include: Option<Patterns>,
+    exclude: Option<Patterns>,
+    respect_ignore_files: bool,
+
+    /// Ignore rules for the directories above the current entry, paired with their depth.
+    ignores: RefCell<Vec<(usize, Gitignore)>>,
+
+    pruned: Cell<u64>,
+    ignored: Cell<u64>,
+    excluded: Cell<u64>,
+}
+
+impl Filter {
+    pub fn new(hidden: SkipHidden) -> Self {
+        Filter {
+            hidden,
+            include: None,
+            exclude: None,
+            respect_ignore_files: false,
+            ignores: RefCell::new(Vec::new()),
+            pruned: Cell::new(0),
+            ignored: Cell::new(0),
+            excluded: Cell::new(0),
+        }
+    }
+
+    pub fn respect_ignore_files(mut self) -> Self {
+        self.respect_ignore_files = true;
+        self
+    }
+
+    pub fn include(mut self, patterns: Patterns) -> Self {
+        self.include = Some(patterns);
+        self
+    }
+
+    pub fn exclude(mut self, patterns: Patterns) -> Self {
+        self.exclude = Some(patterns);
+        self
+    }
+
+    /// The number of hidden entries skipped so far. A pruned directory counts once, however much
+    /// it contains.
+    pub fn pruned(&self) -> u64 {
+        self.pruned.get()
+    }
+
+    /// The number of entries skipped so far by ignore files.
+    pub fn ignored(&self) -> u64 {
+        self.ignored.get()
+    }
+
+    /// The number of entries skipped so far by include and exclude patterns.
+    pub fn excluded(&self) -> u64 {
+        self.excluded.get()
+    }
+
+    pub fn accepts(&self, root: &Path, entry: &DirEntry) -> bool {
+        // The root is whatever the user asked for, whatever it happens to be called.
+        if entry.depth() == 0 {
+            if self.respect_ignore_files {
+                self.ignores.borrow_mut().clear();
+                self.load_ignore_files(entry);
+            }
+            return true;
+        }
+
+        if self.is_skipped_hidden(entry) {
+            self.pruned.set(self.pruned.get() + 1);
+            return false;
+        }
+
+        if self.respect_ignore_files && self.is_ignored(entry) {
+            self.ignored.set(self.ignored.get() + 1);
+            return false;
+        }
+
+        let path = relative_path(root, entry);
+        let is_dir = entry.file_type().is_dir();
+        let excluded = self
+            .exclude
+            .as_ref()
+            .is_some_and(|exclude| exclude.matches(path, is_dir))
+            || !is_dir
+                && self
+                    .include
+                    .as_ref()
+                    .is_some_and(|include| !include.matches(path, is_dir));
+
+        if excluded {
+            self.excluded.set(self.excluded.get() + 1);
+            return false;
+        }
+
+        // The walk is depth first, so the children of this directory are visited next.
+        if is_dir && self.respect_ignore_files {
+            self.load_ignore_files(entry);
+        }
+        true
+    }
+
+    fn is_ignored(&self, entry: &DirEntry) -> bool {
+        let mut ignores = self.ignores.borrow_mut();
+        while ignores
+            .last()
+            .is_some_and(|&(depth, _)| depth >= entry.depth())
+        {
+            ignores.pop();
+        }
+
+        let is_dir = entry.file_type().is_dir();
+        for (_, gitignore) in ignores.iter().rev() {
+            let matched = gitignore.matched(entry.path(), is_dir);
+            if matched.is_ignore() {
+                return true;
+            }
+            if matched.is_whitelist() {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn load_ignore_files(&self, entry: &DirEntry) {
+        let dir = entry.path();
+        let mut builder = GitignoreBuilder::new(dir);
+        let mut found = false;
+
+        for name in IGNORE_FILES {
+            let path = dir.join(name);
+            if path.is_file() {
+                found = true;
+                if let Some(e) = builder.add(&path) {
+                    eprintln!("warning: {}: {}", path.display(), e);
+                }
+            }
+        }
+
+        if found {
+            match builder.build() {
+                Ok(gitignore) => self.ignores.borrow_mut().push((entry.depth(), gitignore)),
+                Err(e) => eprintln!("warning: {}: {}", dir.display(), e),
+            }
+        }
+    }
+
+    fn is_skipped_hidden(&self, entry: &DirEntry) -> bool {
+        let skipped = match self.hidden {
+            SkipHidden::Nothing => return false,
+            SkipHidden::All => true,
+            SkipHidden::Files => !entry.file_type().is_dir(),
+            SkipHidden::Dirs => entry.file_type().is_dir(),
+        };
+        skipped && entry.file_name().to_string_lossy().starts_with('.')
+    }
+}
+
+fn relative_path<'a>(root: &Path, entry: &'a DirEntry) -> &'a Path {
+    entry
+        .path()
+        .strip_prefix(root)
+        .unwrap_or_else(|_| entry.path())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::Scratch, walk};
+
+    /// Builds a tree under a fresh temporary directory. Paths ending in `/` are directories; the
+    /// rest are files with the given contents.
+    fn tree(name: &str, entries: &[(&str, &str)]) -> Scratch {
+        let root = Scratch::new(&format!("filter-{}", name));
+        for &(path, contents) in entries {
+            let path = root.join(path);
+            if path.to_string_lossy().ends_with('/') {
+                fs::create_dir_all(&path).unwrap();
+            } else {
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(&path, contents).unwrap();
+            }
+        }
+        root
+    }
+
+    /// The relative paths a walk of `root` visits, other than the root itself, in sorted order.
+    fn visited(root: &Scratch, filter: &Filter) -> Vec<String> {
+        let mut paths: Vec<_> = walk(root.path(), filter, false)
+            .map(|object| object.unwrap().relative_path)
+            .filter(|path| !path.as_os_str().is_empty())
+            .map(|path| path.to_string_lossy().replace('\\', "/"))
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    fn patterns(patterns: &[&str]) -> Patterns {
+        Patterns::new(patterns.iter().copied()).unwrap()
+    }
+
+    #[test]
+    fn pattern_without_slash_matches_at_any_depth() {
+        let log = patterns(&["*.log"]);
+        assert!(log.matches(Path::new("x.log"), false));
+        assert!(log.matches(Path::new("a/b/x.log"), false));
+        assert!(!log.matches(Path::new("x.log.gz"), false));
+    }
+
+    #[test]
+    fn pattern_with_slash_is_anchored_to_root() {
+        let objects = patterns(&["build/*.o", "/top"]);
+        assert!(objects.matches(Path::new("build/a.o"), false));
+        assert!(!objects.matches(Path::new("src/build/a.o"), false));
+        assert!(!objects.matches(Path::new("build/sub/a.o"), false));
+        assert!(objects.matches(Path::new("top"), true));
+        assert!(!objects.matches(Path::new("a/top"), true));
+    }
+
+    #[test]
+    fn trailing_slash_matches_only_directories() {
+        let cache = patterns(&["cache/"]);
+        assert!(cache.matches(Path::new("cache"), true));
+        assert!(cache.matches(Path::new("a/cache"), true));
+        assert!(!cache.matches(Path::new("cache"), false));
+    }
+
+    #[test]
+    fn pattern_file_skips_blank_lines_and_comments() {
+        let root = tree(
+            "pattern-file",
+            &[("patterns", "# comment\n\n*.log  \nbuild/\n")],
+        );
+        let read = Patterns::from_file(&root.join("patterns"));
+        assert_eq!(read.unwrap(), vec!["*.log", "build/"]);
+    }
+
+    #[test]
+    fn exclude_takes_precedence_over_include() {
+        let root = tree(
+            "exclude-include",
+            &[("keep.txt", ""), ("drop.txt", ""), ("sub/keep.txt", "")],
+        );
+        let filter = Filter::new(SkipHidden::All)
+            .include(patterns(&["*.txt"]))
+            .exclude(patterns(&["drop.txt"]));
+        assert_eq!(
+            visited(&root, &filter),
+            vec!["keep.txt", "sub", "sub/keep.txt"]
+        );
+        assert_eq!(filter.excluded(), 1);
+    }
+
+    #[test]
+    fn include_does_not_prune_directories() {
+        let root = tree("include-dirs", &[("a/b/c.rs", ""), ("a/d.txt", "")]);
+        let filter = Filter::new(SkipHidden::All).include(patterns(&["*.rs"]));
+        assert_eq!(visited(&root, &filter), vec!["a", "a/b", "a/b/c.rs"]);
+        assert_eq!(filter.excluded(), 1);
+    }
+
+    #[test]
+    fn excluded_directory_is_pruned_with_its_contents() {
+        let root = tree(
+            "exclude-dir",
+            &[("target/x", ""), ("target/y/z", ""), ("src/main.rs", "")],
+        );
+        let filter = Filter::new(SkipHidden::All).exclude(patterns(&["target/"]));
+        assert_eq!(visited(&root, &filter), vec!["src", "src/main.rs"]);
+        assert_eq!(filter.excluded(), 1);
+    }
+
+    #[test]
+    fn hidden_entries_are_skipped_before_patterns() {
+        let root = tree(
+            "hidden-first",
+            &[
+                (".hidden.txt", ""),
+                (".git/config", ""),
+                ("visible.txt", ""),
+            ],
+        );
+        let filter = Filter::new(SkipHidden::All)
+            .include(patterns(&["*.txt"]))
+            .exclude(patterns(&[".hidden.txt"]));
+        assert_eq!(visited(&root, &filter), vec!["visible.txt"]);
+        assert_eq!(filter.pruned(), 2);
+        assert_eq!(filter.excluded(), 0);
+    }
+
+    #[test]
+    fn skipping_hidden_files_keeps_hidden_directories() {
+        let root = tree("hidden-files", &[(".dir/file", ""), (".file", "")]);
+        let filter = Filter::new(SkipHidden::Files);
+        assert_eq!(visited(&root, &filter), vec![".dir", ".dir/file"]);
+        assert_eq!(filter.pruned(), 1);
+    }
+
+    #[test]
+    fn root_is_always_visited() {
+        let scratch = tree("root", &[(".root/file", "")]);
+        let root = scratch.join(".root");
+        let filter = Filter::new(SkipHidden::All).exclude(patterns(&["*"]));
+        let count = walk(&root, &filter, false).count();
+        assert_eq!(count, 1);
+        assert_eq!(filter.excluded(), 1);
+    }
+
+    #[test]
+    fn deeper_ignore_files_take_precedence() {
+        let root = tree(
+            "ignore-depth",
+            &[
+                (".gitignore", "*.log\n"),
+                ("sub/.gitignore", "!keep.log\n"),
+                ("a.log", ""),
+                ("b.txt", ""),
+                ("sub/keep.log", ""),
+                ("sub/other.log", ""),
+            ],
+        );
+        let filter = Filter::new(SkipHidden::Nothing).respect_ignore_files();
+        assert_eq!(
+            visited(&root, &filter),
+            vec![
+                ".gitignore",
+                "b.txt",
+                "sub",
+                "sub/.gitignore",
+                "sub/keep.log"
+            ]
+        );
+        assert_eq!(filter.ignored(), 2);
+    }
+
+    #[test]
+    fn later_ignore_files_take_precedence() {
+        let root = tree(
+            "ignore-order",
+            &[
+                (".gitignore", "*.tmp\n"),
+                (".checkedcopyignore", "!wanted.tmp\n"),
+                ("wanted.tmp", ""),
+                ("other.tmp", ""),
+            ],
+        );
+        let filter = Filter::new(SkipHidden::Nothing).respect_ignore_files();
+        assert_eq!(
+            visited(&root, &filter),
+            vec![".checkedcopyignore", ".gitignore", "wanted.tmp"]
+        );
+    }
+
+    #[test]
+    fn ignore_files_apply_before_patterns() {
+        let root = tree("ignore-first", &[(".ignore", "x.txt\n"), ("x.txt", "")]);
+        let filter = Filter::new(SkipHidden::Nothing)
+            .respect_ignore_files()
+            .exclude(patterns(&["x.txt"]));
+        assert_eq!(visited(&root, &filter), vec![".ignore"]);
+        assert_eq!(filter.ignored(), 1);
+        assert_eq!(filter.excluded(), 0);
+    }
+}
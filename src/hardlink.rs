@@ -0,0 +1,75 @@
+//! Recognising files with more than one name, so that they keep sharing their contents at the
+//! destination instead of being copied once per name.
+
+use std::{fs, io, path::Path};
+
+/// The device and inode of a file, which every one of its names shares.
+pub type Key = (u64, u64);
+
+/// The key of the file at `path`, if it has other names.
+#[cfg(unix)]
+pub fn key(path: &Path) -> io::Result<Option<Key>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = fs::metadata(path)?;
+    if metadata.nlink() > 1 {
+        Ok(Some((metadata.dev(), metadata.ino())))
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(not(unix))]
+pub fn key(_path: &Path) -> io::Result<Option<Key>> {
+    Ok(None)
+}
+
+/// Whether `a` and `b` are names for the same file.
+#[cfg(unix)]
+pub fn same_file(a: &Path, b: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let a = fs::symlink_metadata(a)?;
+    let b = fs::symlink_metadata(b)?;
+    Ok(a.dev() == b.dev() && a.ino() == b.ino())
+}
+
+#[cfg(not(unix))]
+pub fn same_file(_a: &Path, _b: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::testing::Scratch;
+
+    #[test]
+    fn every_name_shares_one_key() {
+        let scratch = Scratch::new("hardlink-key");
+        let leader = scratch.join("leader");
+        let follower = scratch.join("follower");
+        fs::write(&leader, "contents").unwrap();
+        assert_eq!(key(&leader).unwrap(), None);
+
+        fs::hard_link(&leader, &follower).unwrap();
+        let leader_key = key(&leader).unwrap();
+        assert!(leader_key.is_some());
+        assert_eq!(key(&follower).unwrap(), leader_key);
+    }
+
+    #[test]
+    fn copies_are_not_the_same_file() {
+        let scratch = Scratch::new("hardlink-same");
+        let leader = scratch.join("leader");
+        let follower = scratch.join("follower");
+        let copy = scratch.join("copy");
+        fs::write(&leader, "contents").unwrap();
+        fs::hard_link(&leader, &follower).unwrap();
+        fs::copy(&leader, &copy).unwrap();
+
+        assert!(same_file(&leader, &follower).unwrap());
+        assert!(!same_file(&leader, &copy).unwrap());
+        assert!(same_file(&leader, &scratch.join("missing")).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+use std::{
+    fmt::{self, Display},
+    fs::File,
+    io::{self, Read, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use sha2::{Digest as _, Sha256};
+use xxhash_rust::xxh3::Xxh3;
+
+const BUFFER_SIZE: usize = 64 * 1024;
+const MAX_LEN: usize = 32;
+
+/// The algorithms available for hashing file contents.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    Blake3,
+    #[default]
+    Sha256,
+    Xxh3,
+    Crc32c,
+}
+
+impl Algorithm {
+    pub const NAMES: &'static [&'static str] = &["blake3", "sha256", "xxh3", "crc32c"];
+    const ALL: [Algorithm; 4] = [
+        Algorithm::Blake3,
+        Algorithm::Sha256,
+        Algorithm::Xxh3,
+        Algorithm::Crc32c,
+    ];
+
+    pub fn hasher(self) -> Box<dyn Hasher> {
+        match self {
+            Algorithm::Blake3 => Box::new(blake3::Hasher::new()),
+            Algorithm::Sha256 => Box::new(Sha256::new()),
+            Algorithm::Xxh3 => Box::new(Xxh3::new()),
+            Algorithm::Crc32c => Box::new(Crc32c(0)),
+        }
+    }
+
+    /// The name used for this algorithm by the BSD-style (`--tag`) output of checksum tools.
+    pub fn tag(self) -> &'static str {
+        match self {
+            Algorithm::Blake3 => "BLAKE3",
+            Algorithm::Sha256 => "SHA256",
+            Algorithm::Xxh3 => "XXH3",
+            Algorithm::Crc32c => "CRC32C",
+        }
+    }
+
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|algorithm| algorithm.tag() == tag)
+    }
+
+    /// The length of a digest in bytes.
+    fn len(self) -> usize {
+        match self {
+            Algorithm::Blake3 | Algorithm::Sha256 => 32,
+            Algorithm::Xxh3 => 8,
+            Algorithm::Crc32c => 4,
+        }
+    }
+}
+
+impl Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Algorithm::Blake3 => "blake3",
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Xxh3 => "xxh3",
+            Algorithm::Crc32c => "crc32c",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blake3" => Ok(Algorithm::Blake3),
+            "sha256" => Ok(Algorithm::Sha256),
+            "xxh3" => Ok(Algorithm::Xxh3),
+            "crc32c" => Ok(Algorithm::Crc32c),
+            _ => Err(format!("unknown hash algorithm: {}", s)),
+        }
+    }
+}
+
+/// Incremental hashing of a stream of bytes with one of the supported algorithms.
+pub trait Hasher {
+    fn update(&mut self, bytes: &[u8]);
+    fn finish(self: Box<Self>) -> Digest;
+}
+
+impl Hasher for blake3::Hasher {
+    fn update(&mut self, bytes: &[u8]) {
+        blake3::Hasher::update(self, bytes);
+    }
+
+    fn finish(self: Box<Self>) -> Digest {
+        Digest::new(Algorithm::Blake3, self.finalize().as_bytes())
+    }
+}
+
+impl Hasher for Sha256 {
+    fn update(&mut self, bytes: &[u8]) {
+        sha2::Digest::update(self, bytes);
+    }
+
+    fn finish(self: Box<Self>) -> Digest {
+        Digest::new(Algorithm::Sha256, self.finalize().as_slice())
+    }
+}
+
+impl Hasher for Xxh3 {
+    fn update(&mut self, bytes: &[u8]) {
+        Xxh3::update(self, bytes);
+    }
+
+    fn finish(self: Box<Self>) -> Digest {
+        Digest::new(Algorithm::Xxh3, &self.digest().to_be_bytes())
+    }
+}
+
+struct Crc32c(u32);
+
+impl Hasher for Crc32c {
+    fn update(&mut self, bytes: &[u8]) {
+        self.0 = crc32c::crc32c_append(self.0, bytes);
+    }
+
+    fn finish(self: Box<Self>) -> Digest {
+        Digest::new(Algorithm::Crc32c, &self.0.to_be_bytes())
+    }
+}
+
+/// A digest of the complete contents of a file. Digests computed with different algorithms are
+/// never equal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Digest {
+    algorithm: Algorithm,
+    bytes: [u8; MAX_LEN],
+}
+
+impl Digest {
+    fn new(algorithm: Algorithm, digest: &[u8]) -> Self {
+        let mut bytes = [0; MAX_LEN];
+        bytes[..digest.len()].copy_from_slice(digest);
+        Digest { algorithm, bytes }
+    }
+
+    pub fn from_path(path: impl AsRef<Path>, algorithm: Algorithm) -> io::Result<Self> {
+        Self::from_reader(File::open(path)?, algorithm)
+    }
+
+    pub fn from_reader(reader: impl Read, algorithm: Algorithm) -> io::Result<Self> {
+        copy(reader, io::sink(), algorithm)
+    }
+
+    /// Parses the hexadecimal form of a digest computed with `algorithm`.
+    pub fn parse(algorithm: Algorithm, s: &str) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid digest");
+        // `from_str_radix` would otherwise let a sign through in place of a digit.
+        if s.len() != algorithm.len() * 2 || !s.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+
+        let mut bytes = [0; MAX_LEN];
+        for (byte, pair) in bytes.iter_mut().zip(s.as_bytes().chunks(2)) {
+            let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+        }
+        Ok(Digest { algorithm, bytes })
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+}
+
+impl Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.bytes[..self.algorithm.len()]
+            .iter()
+            .try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+/// Copies everything from `reader` into `writer`, returning a digest of the bytes written.
+pub fn copy(reader: impl Read, mut writer: impl Write, algorithm: Algorithm) -> io::Result<Digest> {
+    let mut hasher = algorithm.hasher();
+    copy_with(&mut *hasher, reader, &mut writer)?;
+    writer.flush()?;
+    Ok(hasher.finish())
+}
+
+/// Copies everything from `reader` into `writer`, feeding the bytes to `hasher` on the way.
+pub fn copy_with(
+    hasher: &mut dyn Hasher,
+    mut reader: impl Read,
+    mut writer: impl Write,
+) -> io::Result<()> {
+    let mut buf = vec![0; BUFFER_SIZE];
+
+    loop {
+        let len = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        hasher.update(&buf[..len]);
+        writer.write_all(&buf[..len])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_digest_of_each_length() {
+        let cases = [
+            (
+                Algorithm::Blake3,
+                "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff",
+            ),
+            (
+                Algorithm::Sha256,
+                "ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100",
+            ),
+            (Algorithm::Xxh3, "0123456789abcdef"),
+            (Algorithm::Crc32c, "deadbeef"),
+        ];
+        for &(algorithm, hex) in &cases {
+            let digest = Digest::parse(algorithm, hex).unwrap();
+            assert_eq!(digest.algorithm(), algorithm);
+            assert_eq!(digest.to_string(), hex);
+        }
+    }
+
+    #[test]
+    fn parses_upper_case_hex() {
+        assert_eq!(
+            Digest::parse(Algorithm::Crc32c, "DEADBEEF").unwrap(),
+            Digest::parse(Algorithm::Crc32c, "deadbeef").unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_digests() {
+        let cases = [
+            (Algorithm::Crc32c, ""),
+            (Algorithm::Crc32c, "deadbee"),
+            (Algorithm::Crc32c, "deadbeef0"),
+            (Algorithm::Crc32c, "deadbeeg"),
+            (Algorithm::Crc32c, "+eadbeef"),
+            (Algorithm::Crc32c, "deadbé"),
+            (Algorithm::Xxh3, "deadbeef"),
+            (Algorithm::Sha256, "0123456789abcdef"),
+        ];
+        for &(algorithm, hex) in &cases {
+            assert!(
+                Digest::parse(algorithm, hex).is_err(),
+                "accepted {} digest {:?}",
+                algorithm,
+                hex
+            );
+        }
+    }
+
+    #[test]
+    fn digests_of_different_algorithms_differ() {
+        let blake3 = Digest::parse(Algorithm::Blake3, &"00".repeat(32)).unwrap();
+        let sha256 = Digest::parse(Algorithm::Sha256, &"00".repeat(32)).unwrap();
+        assert_ne!(blake3, sha256);
+    }
+
+    #[test]
+    fn names_and_tags_round_trip() {
+        for (&algorithm, &name) in Algorithm::ALL.iter().zip(Algorithm::NAMES) {
+            assert_eq!(algorithm.to_string(), name);
+            assert_eq!(name.parse::<Algorithm>(), Ok(algorithm));
+            assert_eq!(Algorithm::from_tag(algorithm.tag()), Some(algorithm));
+        }
+    }
+}
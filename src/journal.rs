@@ -0,0 +1,271 @@
+//! A record of files already verified at the destination, used to resume interrupted transfers.
+//!
+//! Each line of the journal describes one verified file: the size and modification time of the
+//! source and of the destination at the moment of verification, the digest of the copied bytes
+//! prefixed with its algorithm, as in `sha256:<hex>` (or `-` when no digest was taken), and the
+//! relative path. Entries whose stamps no longer match, and lines that cannot be parsed, are
+//! simply ignored so that the file in question falls back to a full comparison.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use crate::hash::{Algorithm, Digest};
+
+pub const JOURNAL_DIR: &str = ".checked-copy";
+const JOURNAL_FILE: &str = "journal";
+
+/// The size and modification time of a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Stamp {
+    len: u64,
+    modified: Duration,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_err(io::Error::other)?;
+        Ok(Stamp {
+            len: metadata.len(),
+            modified,
+        })
+    }
+
+    fn parse(len: &str, modified: &str) -> Option<Self> {
+        let (secs, nanos) = modified.split_once('.')?;
+        Some(Stamp {
+            len: len.parse().ok()?,
+            modified: Duration::new(secs.parse().ok()?, nanos.parse().ok()?),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Entry {
+    source: Stamp,
+    destination: Stamp,
+    pub digest: Option<Digest>,
+}
+
+pub struct Journal {
+    entries: HashMap<PathBuf, Entry>,
+    file: Mutex<File>,
+}
+
+impl Journal {
+    /// Opens the journal kept in `destination`. Previous entries are loaded when resuming and
+    /// discarded otherwise.
+    pub fn open(destination: &Path, resume: bool) -> io::Result<Self> {
+        let dir = destination.join(JOURNAL_DIR);
+        fs::create_dir_all(&dir)?;
+
+        let path = dir.join(JOURNAL_FILE);
+        let entries = if resume && path.exists() {
+            read_entries(&path)?
+        } else {
+            HashMap::new()
+        };
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(resume)
+            .write(true)
+            .truncate(!resume)
+            .open(&path)?;
+
+        Ok(Journal {
+            entries,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Returns the entry for `path` if it was verified by a previous run and neither copy has
+    /// changed since.
+    pub fn find(&self, path: &Path, source: &Path, destination: &Path) -> Option<&Entry> {
+        let entry = self.entries.get(path)?;
+        let unchanged = matches!(Stamp::of(source), Ok(stamp) if stamp == entry.source)
+            && matches!(Stamp::of(destination), Ok(stamp) if stamp == entry.destination);
+        Some(entry).filter(|_| unchanged)
+    }
+
+    /// Records that `path` has been verified. Paths which cannot be represented in the journal
+    /// are not recorded and will be compared in full on the next run.
+    pub fn record(
+        &self,
+        path: &Path,
+        source: &Path,
+        destination: &Path,
+        digest: Option<Digest>,
+    ) -> io::Result<()> {
+        let path = match path.to_str() {
+            Some(path) if !path.contains('\n') => path,
+            _ => return Ok(()),
+        };
+
+        let source = Stamp::of(source)?;
+        let destination = Stamp::of(destination)?;
+        let digest = digest.map_or_else(
+            || String::from("-"),
+            |digest| format!("{}:{}", digest.algorithm(), digest),
+        );
+        let line = format!(
+            "{}\t{}.{:09}\t{}\t{}.{:09}\t{}\t{}\n",
+            source.len,
+            source.modified.as_secs(),
+            source.modified.subsec_nanos(),
+            destination.len,
+            destination.modified.as_secs(),
+            destination.modified.subsec_nanos(),
+            digest,
+            path,
+        );
+
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes())?;
+        file.flush()
+    }
+}
+
+fn read_entries(path: &Path) -> io::Result<HashMap<PathBuf, Entry>> {
+    let mut entries = HashMap::new();
+    for line in BufReader::new(File::open(path)?).split(b'\n') {
+        let line = line?;
+        if let Some((path, entry)) = std::str::from_utf8(&line).ok().and_then(parse_line) {
+            entries.insert(path, entry);
+        }
+    }
+    Ok(entries)
+}
+
+fn parse_line(line: &str) -> Option<(PathBuf, Entry)> {
+    let mut fields = line.splitn(6, '\t');
+    let source = Stamp::parse(fields.next()?, fields.next()?)?;
+    let destination = Stamp::parse(fields.next()?, fields.next()?)?;
+    let digest = match fields.next()? {
+        "-" => None,
+        digest => {
+            let (algorithm, digest) = digest.split_once(':')?;
+            let algorithm: Algorithm = algorithm.parse().ok()?;
+            Some(Digest::parse(algorithm, digest).ok()?)
+        }
+    };
+
+    let path = fields.next().filter(|path| !path.is_empty())?;
+    Some((
+        PathBuf::from(path),
+        Entry {
+            source,
+            destination,
+            digest,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scratch;
+
+    const DIGEST: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    fn stamp(len: u64, secs: u64, nanos: u32) -> Stamp {
+        Stamp {
+            len,
+            modified: Duration::new(secs, nanos),
+        }
+    }
+
+    #[test]
+    fn parses_line_with_digest() {
+        let line = format!(
+            "12\t1600000000.000000001\t12\t1600000001.500000000\tsha256:{}\tdir/file.txt",
+            DIGEST
+        );
+        let (path, entry) = parse_line(&line).unwrap();
+        assert_eq!(path, PathBuf::from("dir/file.txt"));
+        assert_eq!(entry.source, stamp(12, 1_600_000_000, 1));
+        assert_eq!(entry.destination, stamp(12, 1_600_000_001, 500_000_000));
+        assert_eq!(
+            entry.digest,
+            Some(Digest::parse(Algorithm::Sha256, DIGEST).unwrap())
+        );
+    }
+
+    #[test]
+    fn parses_line_without_digest() {
+        let (path, entry) = parse_line("0\t1.000000000\t0\t2.000000000\t-\tempty").unwrap();
+        assert_eq!(path, PathBuf::from("empty"));
+        assert_eq!(entry.digest, None);
+    }
+
+    #[test]
+    fn keeps_tabs_in_path() {
+        let (path, _) = parse_line("0\t1.0\t0\t1.0\t-\ta\tb").unwrap();
+        assert_eq!(path, PathBuf::from("a\tb"));
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        let digest = format!("sha256:{}", DIGEST);
+        let lines = [
+            String::new(),
+            String::from("0\t1.0\t0\t1.0\t-"),
+            String::from("0\t1.0\t0\t1.0\t-\t"),
+            String::from("x\t1.0\t0\t1.0\t-\tpath"),
+            String::from("0\t1\t0\t1.0\t-\tpath"),
+            String::from("0\t1.0\t0\t1.0\tsha256\tpath"),
+            format!("0\t1.0\t0\t1.0\tmd5:{}\tpath", DIGEST),
+            format!("0\t1.0\t0\t1.0\t{}ff\tpath", digest),
+            format!("0\t1.0\t0\t1.0\txxh3:{}\tpath", DIGEST),
+        ];
+        for line in &lines {
+            assert!(parse_line(line).is_none(), "accepted {:?}", line);
+        }
+    }
+
+    #[test]
+    fn finds_recorded_entries_after_reopening() {
+        let scratch = Scratch::new("journal");
+        let root = scratch.path();
+        let source = root.join("source");
+        let destination = root.join("destination");
+        fs::write(&source, b"contents").unwrap();
+        fs::write(&destination, b"contents").unwrap();
+        let digest = Digest::from_path(&source, Algorithm::Blake3).unwrap();
+
+        let journal = Journal::open(root, false).unwrap();
+        journal
+            .record(Path::new("copied"), &source, &destination, Some(digest))
+            .unwrap();
+        journal
+            .record(Path::new("line\nbreak"), &source, &destination, None)
+            .unwrap();
+        drop(journal);
+
+        let journal = Journal::open(root, true).unwrap();
+        let entry = journal.find(Path::new("copied"), &source, &destination);
+        let missing = journal
+            .find(Path::new("line\nbreak"), &source, &destination)
+            .is_none();
+        let found = entry.map(|entry| entry.digest);
+
+        fs::write(&destination, b"changed").unwrap();
+        let stale = journal
+            .find(Path::new("copied"), &source, &destination)
+            .is_some();
+        drop(journal);
+
+        assert_eq!(found, Some(Some(digest)));
+        assert!(missing);
+        assert!(!stale);
+    }
+}
@@ -1,32 +1,341 @@
+mod audit;
+mod backup;
+mod compare;
+mod conflict;
+mod durable;
+mod error;
+mod filter;
+mod hardlink;
+mod hash;
+mod journal;
+mod manifest;
+mod preserve;
+mod sparse;
+mod special;
+mod symlink;
+mod temp;
+#[cfg(test)]
+mod testing;
+mod verify;
+mod xattr;
+
 use std::{
+    borrow::Cow,
+    collections::{hash_map, HashMap},
     fmt::Display,
-    fs::{self, FileType},
+    fs::{self, File, FileType, Permissions},
     io,
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc, Mutex,
+    },
+    thread,
 };
 
-use imprint::Imprint;
-use structopt::StructOpt;
+use backup::Backup;
+use compare::{Comparison, Match};
+use conflict::{Policy, Resolution};
+use durable::Uncached;
+use error::{BadCopy, Error, Result};
+use filter::{Filter, Patterns, SkipHidden};
+use hash::{Algorithm, Digest};
+use journal::{Journal, JOURNAL_DIR};
+use manifest::Manifest;
+use preserve::{Attribute, Preserve};
+use special::Kind;
+use structopt::{clap::AppSettings, StructOpt};
+use symlink::Symlinks;
 use walkdir::{DirEntry, WalkDir};
+use xattr::{Attributes, Unset};
 
 #[derive(Clone, Debug, StructOpt)]
+#[structopt(
+    setting = AppSettings::SubcommandsNegateReqs,
+    setting = AppSettings::ArgsNegateSubcommands
+)]
 struct Opts {
-    source: String,
-    destination: String,
+    #[structopt(required = true)]
+    source: Option<String>,
+    #[structopt(required = true)]
+    destination: Option<String>,
 
     /// copy hidden files (starting with .dot)
     #[structopt(short = "h", long = "hidden")]
     include_hidden_files: bool,
 
+    /// which hidden entries to skip when not copying them; hidden directories are skipped
+    /// along with everything they contain
+    #[structopt(
+        long = "skip-hidden",
+        default_value = "all",
+        possible_values = &["files", "dirs", "all"]
+    )]
+    skip_hidden: SkipHidden,
+
+    /// only copy files matching this glob; may be repeated
+    #[structopt(long = "include", number_of_values = 1)]
+    include: Vec<String>,
+
+    /// skip entries matching this glob, and everything beneath a matching directory; may be
+    /// repeated, and takes precedence over --include
+    #[structopt(long = "exclude", number_of_values = 1)]
+    exclude: Vec<String>,
+
+    /// read exclude patterns from a file, one per line
+    #[structopt(long = "exclude-from", number_of_values = 1, parse(from_os_str))]
+    exclude_from: Vec<PathBuf>,
+
+    /// skip whatever .gitignore, .ignore and .checkedcopyignore files in the source ignore;
+    /// hidden entries are then left to those files rather than skipped automatically
+    #[structopt(long = "respect-ignore")]
+    respect_ignore: bool,
+
+    /// what to do with symlinks: preserve them with their original targets, follow them and copy
+    /// what they point to, or skip them; links that form a loop or point nowhere are skipped
+    /// when following
+    #[structopt(
+        long = "symlinks",
+        default_value = "skip",
+        possible_values = &["preserve", "follow", "skip"]
+    )]
+    symlinks: Symlinks,
+
+    /// what to do with FIFOs, sockets and device nodes: recreate them where the system allows it,
+    /// skip them with a warning, or fail
+    #[structopt(
+        long = "special",
+        default_value = "skip",
+        possible_values = &["recreate", "skip", "fail"]
+    )]
+    special: special::Policy,
+
     /// remove moved files
     #[structopt(short = "r", long = "remove")]
     remove_copied_files: bool,
+
+    /// what to do when a destination file differs from its source: overwrite, skip, rename,
+    /// newer, larger or fail
+    #[structopt(
+        long = "on-conflict",
+        default_value = "overwrite",
+        possible_values = &["overwrite", "skip", "rename", "newer", "larger", "fail"]
+    )]
+    on_conflict: Policy,
+
+    /// keep overwritten files: alone, as `name.~n~`; with a suffix, as `name<suffix>`; with a
+    /// path (anything containing a separator), moved into that directory
+    #[structopt(long = "backup", require_equals = true, value_name = "suffix|dir")]
+    backup: Option<Option<Backup>>,
+
+    /// fsync every file and directory written, and read the destination back from the device
+    /// before removing a source
+    #[structopt(long = "durable")]
+    durable: bool,
+
+    /// read files back from the device rather than the page cache when verifying them, by
+    /// evicting cached pages (the default) or with direct I/O
+    #[structopt(long = "uncached", require_equals = true, value_name = "evict|direct")]
+    uncached: Option<Option<Uncached>>,
+
+    /// the algorithm used to hash file contents, sha256 unless given; files already in place are
+    /// compared by digests made with it rather than by sampled imprint
+    #[structopt(long = "hash", possible_values = Algorithm::NAMES)]
+    hash: Option<Algorithm>,
+
+    /// compare files byte for byte wherever they would otherwise be compared by imprint or
+    /// digest, and compare each copy with its source byte for byte as well
+    #[structopt(long = "full")]
+    full: bool,
+
+    /// keep these attributes of sources on their copies, separated by commas: mode, timestamps,
+    /// ownership or all; file permissions are kept regardless
+    #[structopt(
+        long = "preserve",
+        use_delimiter = true,
+        value_name = "attributes",
+        possible_values = &["mode", "timestamps", "ownership", "all"]
+    )]
+    preserve: Vec<Attribute>,
+
+    /// copy extended attributes, including SELinux labels
+    #[structopt(long = "xattrs")]
+    xattrs: bool,
+
+    /// copy POSIX ACLs
+    #[structopt(long = "acls")]
+    acls: bool,
+
+    /// count a file as failed when any of its attributes could not be set
+    #[structopt(long = "strict")]
+    strict: bool,
+
+    /// report what would be done without touching the filesystem
+    #[structopt(short = "n", long = "dry-run")]
+    dry_run: bool,
+
+    /// number of files to copy and verify concurrently
+    #[structopt(short = "j", long = "jobs", default_value = "1")]
+    jobs: usize,
+
+    /// skip files verified by a previous, interrupted run, unless comparing with --full
+    #[structopt(long = "resume")]
+    resume: bool,
+
+    /// write a manifest of every verified file, in the tagged format of `sha256sum --tag`
+    #[structopt(long = "manifest", parse(from_os_str))]
+    manifest: Option<PathBuf>,
+
+    /// keep copying after a failure and report every failure at the end
+    #[structopt(short = "k", long = "keep-going")]
+    keep_going: bool,
+
+    /// skip entries that cannot be read during the walk instead of failing
+    #[structopt(long = "ignore-unreadable")]
+    ignore_unreadable: bool,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
 impl Opts {
+    fn jobs(&self) -> usize {
+        self.jobs.max(1)
+    }
+
+    fn source(&self) -> &Path {
+        self.source.as_deref().unwrap_or_default().as_ref()
+    }
+
+    fn uncached(&self) -> Option<Uncached> {
+        self.uncached.map(|mode| mode.unwrap_or(Uncached::Evict))
+    }
+
+    fn hash(&self) -> Algorithm {
+        self.hash.unwrap_or_default()
+    }
+
+    fn comparison(&self) -> Comparison {
+        Comparison {
+            algorithm: self.hash,
+            full: self.full,
+            uncached: self.uncached(),
+        }
+    }
+
+    fn attributes(&self) -> Attributes {
+        Attributes::new(self.xattrs, self.acls)
+    }
+
+    fn preserve(&self) -> Preserve {
+        Preserve::new(&self.preserve)
+    }
+
+    fn backup(&self) -> Option<Backup> {
+        self.backup
+            .clone()
+            .map(|backup| backup.unwrap_or(Backup::Numbered))
+    }
+
+    fn filter(&self) -> Result<Filter> {
+        let mut filter = if self.respect_ignore {
+            Filter::new(SkipHidden::Nothing).respect_ignore_files()
+        } else {
+            Filter::new(hidden_policy(self.include_hidden_files, self.skip_hidden))
+        };
+        if !self.include.is_empty() {
+            filter = filter.include(Patterns::new(self.include.iter().map(String::as_str))?);
+        }
+
+        let mut exclude = self.exclude.clone();
+        for path in &self.exclude_from {
+            exclude.extend(Patterns::from_file(path)?);
+        }
+        if !exclude.is_empty() {
+            filter = filter.exclude(Patterns::new(exclude.iter().map(String::as_str))?);
+        }
+
+        Ok(filter)
+    }
+
+    fn destination(&self) -> &Path {
+        self.destination.as_deref().unwrap_or_default().as_ref()
+    }
+}
+
+#[derive(Clone, Debug, StructOpt)]
+enum Command {
+    /// compare an existing destination with its source without copying anything
+    Verify(VerifyOpts),
+
+    /// check a destination against a previously written manifest
+    Audit(AuditOpts),
+}
+
+#[derive(Clone, Debug, StructOpt)]
+struct VerifyOpts {
+    source: String,
+    destination: String,
+
+    /// compare hidden files (starting with .dot)
+    #[structopt(short = "h", long = "hidden")]
+    include_hidden_files: bool,
+
+    /// compare files by digests made with this algorithm instead of by sampled imprint
+    #[structopt(long = "hash", possible_values = Algorithm::NAMES)]
+    hash: Option<Algorithm>,
+
+    /// compare files byte for byte instead of by imprint or digest
+    #[structopt(long = "full")]
+    full: bool,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+struct AuditOpts {
+    #[structopt(parse(from_os_str))]
+    manifest: PathBuf,
+    #[structopt(parse(from_os_str))]
+    root: PathBuf,
+
+    /// report unexpected hidden files (starting with .dot)
+    #[structopt(short = "h", long = "hidden")]
+    include_hidden_files: bool,
+}
+
+impl AuditOpts {
+    fn filter(&self) -> Filter {
+        Filter::new(hidden_policy(self.include_hidden_files, SkipHidden::All))
+    }
+}
+
+fn hidden_policy(include_hidden_files: bool, skip_hidden: SkipHidden) -> SkipHidden {
+    if include_hidden_files {
+        SkipHidden::Nothing
+    } else {
+        skip_hidden
+    }
+}
+
+impl VerifyOpts {
+    fn source(&self) -> &Path {
+        self.source.as_ref()
+    }
+
+    fn filter(&self) -> Filter {
+        Filter::new(hidden_policy(self.include_hidden_files, SkipHidden::All))
+    }
+
     fn destination(&self) -> &Path {
         self.destination.as_ref()
     }
+
+    fn comparison(&self) -> Comparison {
+        Comparison {
+            algorithm: self.hash,
+            full: self.full,
+            uncached: None,
+        }
+    }
 }
 
 struct Object {
@@ -36,113 +345,1040 @@ struct Object {
 }
 
 impl Object {
-    fn new(base_path: impl AsRef<Path>, entry: DirEntry) -> io::Result<Self> {
+    fn new(base_path: impl AsRef<Path>, entry: DirEntry) -> Self {
         let absolute_path = entry.path().to_owned();
         let relative_path = absolute_path.strip_prefix(base_path).unwrap().to_owned();
-        Ok(Object {
+        Object {
             file_type: entry.file_type(),
             absolute_path,
             relative_path,
-        })
+        }
     }
 
-    fn copy_to(&self, destination: &Path) -> io::Result<()> {
+    /// Copies this object to `destination` in a single pass, returning a digest of the bytes
+    /// actually written along with the source's permissions, which are left for the caller to
+    /// apply once the copy is otherwise finished. With `--durable`, the copy is flushed to the
+    /// device before returning.
+    fn copy_to(&self, destination: &Path, opts: &Opts) -> io::Result<(Digest, Permissions)> {
         if self.absolute_path == destination {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "attempt to copy to self",
-            ));
+            return Err(io::Error::other("attempt to copy to self"));
         }
 
-        Ok({
-            fs::copy(&self.absolute_path, destination)?;
-        })
+        let mut source = File::open(&self.absolute_path)?;
+        let metadata = source.metadata()?;
+        let mut file = File::create(destination)?;
+        let digest = sparse::copy_file(&mut source, &mut file, metadata.len(), opts.hash())?;
+        if opts.durable {
+            file.sync_all()?;
+        }
+        Ok((digest, metadata.permissions()))
     }
 }
 
-#[derive(Clone, Debug)]
-struct BadCopy {
-    source: PathBuf,
-    destination: PathBuf,
+/// State shared by every worker during a run.
+struct Context<'a> {
+    opts: &'a Opts,
+    backup: Option<Backup>,
+    journal: Option<Journal>,
+    manifest: Option<Manifest>,
+
+    /// The total length of the files copied, and the space they take up at the destination.
+    apparent: AtomicU64,
+    allocated: AtomicU64,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Action {
+    Create,
+    Copy,
+    Link,
+    Exists,
+    Skip,
+    SkipLink,
+    Remove,
 }
 
-impl BadCopy {
-    fn new(source: impl Into<PathBuf>, destination: impl Into<PathBuf>) -> Self {
-        Self {
-            source: source.into(),
-            destination: destination.into(),
+impl Action {
+    fn describe(self, dry_run: bool) -> &'static str {
+        match (self, dry_run) {
+            (Action::Create, false) => "created",
+            (Action::Copy, false) => "copied",
+            (Action::Link, false) => "linked",
+            (Action::Exists, _) => "exists",
+            (Action::Skip, false) => "skipped",
+            (Action::SkipLink, false) => "skipped link",
+            (Action::Remove, false) => "removed",
+            (Action::Create, true) => "would create",
+            (Action::Copy, true) => "would copy",
+            (Action::Link, true) => "would link",
+            (Action::Skip, true) => "would skip",
+            (Action::SkipLink, true) => "would skip link",
+            (Action::Remove, true) => "would remove",
         }
     }
 }
 
-impl Display for BadCopy {
+#[derive(Debug)]
+struct Failure {
+    path: PathBuf,
+    error: Error,
+}
+
+#[derive(Debug, Default)]
+struct Summary {
+    created: u64,
+    copied: u64,
+    linked: u64,
+    existing: u64,
+    skipped: u64,
+    removed: u64,
+    special: special::Counts,
+    failures: Vec<Failure>,
+}
+
+impl Summary {
+    fn record(&mut self, action: Action, path: &Path, dry_run: bool) {
+        match action {
+            Action::Create => self.created += 1,
+            Action::Copy => self.copied += 1,
+            Action::Link => self.linked += 1,
+            Action::Exists => self.existing += 1,
+            Action::Skip | Action::SkipLink => self.skipped += 1,
+            Action::Remove => self.removed += 1,
+        }
+        println!("{} {}", action.describe(dry_run), path.display());
+    }
+
+    fn fail(&mut self, path: &Path, error: Error) {
+        eprintln!("failed {}: {}", path.display(), error);
+        self.failures.push(Failure {
+            path: path.to_owned(),
+            error,
+        });
+    }
+
+    fn print_failures(&self) {
+        let width = self
+            .failures
+            .iter()
+            .map(|failure| failure.error.operation().len())
+            .max()
+            .unwrap_or_default();
+
+        eprintln!("{} failures:", self.failures.len());
+        for failure in &self.failures {
+            match failure.error.io_error() {
+                Some(e) => eprintln!(
+                    "  {:<width$}  {}  {}",
+                    failure.error.operation(),
+                    failure.path.display(),
+                    e,
+                    width = width
+                ),
+                None => eprintln!(
+                    "  {:<width$}  {}",
+                    failure.error.operation(),
+                    failure.path.display(),
+                    width = width
+                ),
+            }
+        }
+    }
+}
+
+impl Display for Summary {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(
+        write!(
             f,
-            "bad copy:\n  source: {}\n  destination: {}",
-            self.source.display(),
-            self.destination.display()
+            "{} created, {} copied, {} linked, {} exists, {} skipped, {} removed, {} failed",
+            self.created,
+            self.copied,
+            self.linked,
+            self.existing,
+            self.skipped,
+            self.removed,
+            self.failures.len()
         )
     }
 }
 
-impl std::error::Error for BadCopy {}
-
 fn main() {
     let opts = Opts::from_args();
-    if let Err(e) = run(&opts) {
+    let result = match &opts.command {
+        Some(Command::Verify(verify_opts)) => verify::verify(verify_opts),
+        Some(Command::Audit(audit_opts)) => audit::audit(audit_opts),
+        None => run(&opts),
+    };
+
+    if let Err(e) = result {
         eprintln!("{}", e);
-        std::process::exit(1);
+        std::process::exit(e.exit_code());
     }
 }
 
-fn run(opts: &Opts) -> io::Result<()> {
-    let source_entries = WalkDir::new(&opts.source).into_iter().filter_map(|entry| {
-        let entry = entry.ok()?;
-        if !opts.include_hidden_files && entry.file_name().to_string_lossy().starts_with('.') {
-            return None;
+/// Walks the tree beneath `root`, yielding each entry accepted by `filter` with its path
+/// relative to `root`, along with any entry that could not be read. With `follow_links`, symlinks
+/// are walked as whatever they point to, and links leading back to an ancestor are errors.
+fn walk<'a>(
+    root: &'a Path,
+    filter: &'a Filter,
+    follow_links: bool,
+) -> impl Iterator<Item = Result<Object>> + 'a {
+    WalkDir::new(root)
+        .follow_links(follow_links)
+        .into_iter()
+        .filter_entry(move |entry| filter.accepts(root, entry))
+        .map(move |entry| {
+            entry
+                .map(|entry| Object::new(root, entry))
+                .map_err(Error::walk(root))
+        })
+}
+
+fn run(opts: &Opts) -> Result<()> {
+    let filter = opts.filter()?;
+    let follow_links = opts.symlinks == Symlinks::Follow;
+    let source_entries = walk(opts.source(), &filter, follow_links);
+
+    let journal = if opts.dry_run {
+        None
+    } else {
+        let journal = Journal::open(opts.destination(), opts.resume)
+            .map_err(Error::journal(opts.destination()))?;
+        Some(journal)
+    };
+
+    let manifest = match &opts.manifest {
+        Some(_) if !opts.dry_run => Some(Manifest::default()),
+        _ => None,
+    };
+
+    let cx = Context {
+        opts,
+        backup: opts.backup(),
+        journal,
+        manifest,
+        apparent: AtomicU64::new(0),
+        allocated: AtomicU64::new(0),
+    };
+    let mut summary = Summary::default();
+    let preserve = opts.preserve();
+    let mut directories = Vec::new();
+    let mut stale = 0;
+    let mut leaders: HashMap<hardlink::Key, PathBuf> = HashMap::new();
+    let mut followers = Vec::new();
+    let mut outcomes: HashMap<PathBuf, Option<Placed>> = HashMap::new();
+    let failed = AtomicBool::new(false);
+    let (job_tx, job_rx) = mpsc::sync_channel::<(Object, PathBuf)>(opts.jobs() * 2);
+    let (result_tx, result_rx) = mpsc::channel();
+    let job_rx = Mutex::new(job_rx);
+
+    let result: Result<()> = thread::scope(|scope| {
+        for _ in 0..opts.jobs() {
+            let cx = &cx;
+            let job_rx = &job_rx;
+            let failed = &failed;
+            let result_tx = result_tx.clone();
+            scope.spawn(move || loop {
+                let job = job_rx.lock().unwrap().recv();
+                let (object, destination) = match job {
+                    Ok(job) => job,
+                    Err(_) => break,
+                };
+
+                // Once any file has failed, drain the queue without doing more work.
+                if failed.load(Ordering::SeqCst) {
+                    continue;
+                }
+
+                let result = place(cx, &object, &destination);
+                if result.is_err() && !opts.keep_going {
+                    failed.store(true, Ordering::SeqCst);
+                }
+                if result_tx.send((object, result)).is_err() {
+                    break;
+                }
+            });
         }
+        drop(result_tx);
 
-        Object::new(&opts.source, entry).ok()
-    });
+        // Records the outcome of a file, returning the error only when it should end the run.
+        let record = |summary: &mut Summary, path: &Path, result: Result<Action>| {
+            match result {
+                Ok(action) => {
+                    summary.record(action, path, opts.dry_run);
+                    if opts.remove_copied_files
+                        && matches!(action, Action::Copy | Action::Link | Action::Exists)
+                    {
+                        summary.record(Action::Remove, path, opts.dry_run);
+                    }
+                }
+                Err(e) if opts.keep_going => summary.fail(path, e),
+                Err(e) => {
+                    failed.store(true, Ordering::SeqCst);
+                    return Err(e);
+                }
+            }
+            Ok(())
+        };
 
-    for object in source_entries {
-        let destination = opts.destination().join(&object.relative_path);
+        for entry in source_entries {
+            let object = match entry {
+                Ok(object) => object,
+                // Followed links that loop back on themselves, or that lead nowhere.
+                Err(e) if follow_links && e.is_broken_link() => {
+                    let path = e.walk_path().unwrap_or_else(|| opts.source());
+                    let path = path.strip_prefix(opts.source()).unwrap_or(path).to_owned();
+                    eprintln!("warning: {}", e);
+                    record(&mut summary, &path, Ok(Action::SkipLink))?;
+                    continue;
+                }
+                Err(e) if opts.ignore_unreadable => {
+                    eprintln!("skipped {}", e);
+                    continue;
+                }
+                Err(e) => {
+                    let path = e.walk_path().unwrap_or_else(|| opts.source()).to_owned();
+                    record(&mut summary, &path, Err(e))?;
+                    continue;
+                }
+            };
 
-        if object.file_type.is_dir() {
-            if !destination.exists() {
-                fs::create_dir_all(&destination)?;
-                println!("created {}", object.relative_path.display());
+            // A source that was itself a destination has a journal of its own, which must not be
+            // copied over the one in use.
+            if object.relative_path.starts_with(JOURNAL_DIR) {
+                continue;
+            }
+
+            let destination = opts.destination().join(&object.relative_path);
+
+            // Links are cheap enough to handle here rather than in a worker.
+            if object.file_type.is_symlink() {
+                let result = match opts.symlinks {
+                    Symlinks::Skip => Ok(Action::SkipLink),
+                    Symlinks::Preserve | Symlinks::Follow => copy_link(&cx, &object, &destination),
+                };
+                record(&mut summary, &object.relative_path, result)?;
+                continue;
+            }
+
+            if let Some(kind) = Kind::of(object.file_type) {
+                summary.special.add(kind);
+                let result = match opts.special {
+                    special::Policy::Recreate => recreate_special(&cx, &object, &destination, kind),
+                    special::Policy::Skip => {
+                        eprintln!(
+                            "warning: skipped {} {}",
+                            kind,
+                            object.relative_path.display()
+                        );
+                        Ok(Action::Skip)
+                    }
+                    special::Policy::Fail => Err(Error::Special {
+                        path: object.absolute_path.clone(),
+                        kind: kind.name(),
+                    }),
+                };
+                // The source node goes once it has been made, like any other copied file.
+                let created = matches!(result, Ok(Action::Create));
+                record(&mut summary, &object.relative_path, result)?;
+                if created && opts.remove_copied_files {
+                    summary.record(Action::Remove, &object.relative_path, opts.dry_run);
+                }
+                continue;
             }
-            continue;
-        }
 
-        if object.file_type.is_file() {
-            let source_imprint = Imprint::new(&object.absolute_path)?;
-            if destination.exists() && source_imprint == Imprint::new(&destination)? {
-                println!("exists {}", object.relative_path.display());
-                if opts.remove_copied_files {
-                    fs::remove_file(&object.absolute_path)?;
+            // Directories are created here, in walk order, so that a directory always exists
+            // before any file beneath it is handed to a worker.
+            if object.file_type.is_dir() {
+                if destination.is_dir() {
+                    if !opts.dry_run {
+                        match temp::remove_stale(&destination) {
+                            Ok(removed) => stale += removed,
+                            Err(e) => record(&mut summary, &object.relative_path, Err(e))?,
+                        }
+                    }
+                } else if !destination.exists() {
+                    let result = if opts.dry_run {
+                        Ok(Action::Create)
+                    } else {
+                        create_dir(opts, &object.absolute_path, &destination)
+                            .map(|_| Action::Create)
+                    };
+                    record(&mut summary, &object.relative_path, result)?;
+                }
+
+                if !opts.dry_run && !preserve.is_empty() && destination.is_dir() {
+                    match fs::metadata(&object.absolute_path) {
+                        Ok(metadata) => {
+                            directories.push((object.relative_path, destination, metadata))
+                        }
+                        Err(e) => {
+                            let e = Error::metadata(&object.absolute_path)(e);
+                            record(&mut summary, &object.relative_path, Err(e))?;
+                        }
+                    }
                 }
                 continue;
             }
 
-            object.copy_to(&destination)?;
-            let destination_imprint = Imprint::new(&destination)?;
-            if source_imprint != destination_imprint {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    BadCopy::new(object.absolute_path, destination),
-                ));
+            if object.file_type.is_file() {
+                // Only the first name met for a file is copied; the others are linked to it once
+                // that copy is finished.
+                if let Some(key) = hardlink::key(&object.absolute_path).ok().flatten() {
+                    match leaders.entry(key) {
+                        hash_map::Entry::Occupied(leader) => {
+                            followers.push((object, destination, leader.get().clone()));
+                            continue;
+                        }
+                        hash_map::Entry::Vacant(leader) => {
+                            leader.insert(object.relative_path.clone());
+                            outcomes.insert(object.relative_path.clone(), None);
+                        }
+                    }
+                }
+
+                if job_tx.send((object, destination)).is_err() {
+                    break;
+                }
+            }
+
+            for (object, result) in result_rx.try_iter() {
+                let result = settle(&mut outcomes, &object, result);
+                record(&mut summary, &object.relative_path, result)?;
+            }
+        }
+
+        drop(job_tx);
+        for (object, result) in result_rx {
+            let result = settle(&mut outcomes, &object, result);
+            record(&mut summary, &object.relative_path, result)?;
+        }
+
+        // A name whose first copy was skipped or failed is copied in its own right.
+        for (object, destination, leader) in followers {
+            let result = match outcomes.get(&leader) {
+                Some(Some(leader)) => link_or_copy(&cx, &object, &destination, leader),
+                _ => transfer(&cx, &object, &destination),
+            };
+            record(&mut summary, &object.relative_path, result)?;
+        }
+
+        Ok(())
+    });
+
+    // Whatever was verified before a failure is still worth recording.
+    if let (Some(manifest), Some(path)) = (cx.manifest, &opts.manifest) {
+        manifest.write(path).map_err(Error::manifest(path))?;
+    }
+    result?;
+
+    // Writing into a directory changes its times, so they are only set once everything beneath
+    // it is in place, deepest directories first.
+    for (path, destination, metadata) in directories.iter().rev() {
+        if let Err(e) = preserve
+            .apply_ownership(metadata, destination)
+            .and_then(|unset| preserve.apply(metadata, destination).map(|()| unset))
+            .map_err(Error::preserve(destination))
+            .and_then(|unset| check_attributes(opts, destination, unset))
+        {
+            if !opts.keep_going {
+                return Err(e);
+            }
+            summary.fail(path, e);
+        }
+    }
+
+    if stale > 0 {
+        println!("removed {} stale temporary files", stale);
+    }
+    if filter.pruned() > 0 {
+        println!("pruned {} hidden entries", filter.pruned());
+    }
+    if filter.ignored() > 0 {
+        println!("ignored {} entries", filter.ignored());
+    }
+    if filter.excluded() > 0 {
+        println!("excluded {} entries", filter.excluded());
+    }
+
+    if summary.special.total() > 0 {
+        println!("special files: {}", summary.special);
+    }
+
+    if opts.dry_run {
+        println!("{}", summary);
+    } else if summary.copied > 0 {
+        println!(
+            "{} bytes copied, {} bytes allocated",
+            cx.apparent.into_inner(),
+            cx.allocated.into_inner()
+        );
+    }
+
+    if !summary.failures.is_empty() {
+        summary.print_failures();
+        return Err(Error::Failures(summary.failures.len()));
+    }
+
+    Ok(())
+}
+
+/// Notes where the copy of a file with other names was placed, for linking those names to it.
+fn settle(
+    outcomes: &mut HashMap<PathBuf, Option<Placed>>,
+    object: &Object,
+    result: Result<(Action, Option<Placed>)>,
+) -> Result<Action> {
+    result.map(|(action, placed)| {
+        if let Some(outcome) = outcomes.get_mut(&object.relative_path) {
+            *outcome = placed;
+        }
+        action
+    })
+}
+
+/// Copies and verifies a single file, returning whether it was copied, already present or
+/// skipped.
+fn transfer(cx: &Context, object: &Object, destination: &Path) -> Result<Action> {
+    place(cx, object, destination).map(|(action, _)| action)
+}
+
+/// Where a file's contents ended up at the destination, and their digest if one was taken.
+#[derive(Clone, Debug)]
+struct Placed {
+    path: PathBuf,
+    digest: Option<Digest>,
+}
+
+/// Does the work of `transfer`, also returning where the contents were placed unless the file
+/// was skipped.
+fn place(cx: &Context, object: &Object, destination: &Path) -> Result<(Action, Option<Placed>)> {
+    let opts = cx.opts;
+    let source = object.absolute_path.as_path();
+    let previous = cx
+        .journal
+        .as_ref()
+        // The journal only records that a file was checked, not how, so `--full` checks again.
+        .filter(|_| opts.resume && !opts.full)
+        .and_then(|journal| journal.find(&object.relative_path, source, destination));
+
+    let comparison = opts.comparison();
+    let matched = match previous {
+        None if destination.exists() => comparison.matches(source, destination)?,
+        _ => None,
+    };
+    if previous.is_some() || matched.is_some() {
+        let digest = matched.and_then(Match::digest);
+        let digest = existing(cx, object, destination, previous, digest)?;
+        return Ok(placed(Action::Exists, destination, digest));
+    }
+
+    let mut destination = Cow::Borrowed(destination);
+    if destination.exists() {
+        let resolution = conflict::resolve(opts.on_conflict, source, &destination, |candidate| {
+            Ok(comparison.matches(source, candidate)?.is_some())
+        })?;
+        println!(
+            "conflict {}: {}",
+            object.relative_path.display(),
+            resolution.describe(opts.dry_run)
+        );
+
+        match resolution {
+            Resolution::Overwrite => (),
+            Resolution::Keep(_) => return Ok((Action::Skip, None)),
+            Resolution::Rename(path) => destination = Cow::Owned(path),
+            Resolution::Existing(path) => {
+                let digest = existing(cx, object, &path, None, None)?;
+                return Ok(placed(Action::Exists, &path, digest));
+            }
+        }
+    }
+
+    let mut digest = None;
+    if !opts.dry_run {
+        let destination = destination.as_ref();
+        let temp = temp::path(destination);
+        let (source_digest, unset) = match copy_and_verify(opts, object, destination, &temp) {
+            Ok(copied) => copied,
+            Err(e) => {
+                let _ = fs::remove_file(&temp);
+                return Err(e);
             }
+        };
 
-            println!("copied {}", object.relative_path.display());
+        if let Some(backup) = cx.backup.as_ref().filter(|_| destination.exists()) {
+            let target = backup
+                .back_up(opts.destination(), destination)
+                .map_err(Error::backup(destination))?;
+            println!(
+                "backed up {} to {}",
+                object.relative_path.display(),
+                target.display()
+            );
+        }
+
+        fs::rename(&temp, destination).map_err(Error::copy(&temp, destination))?;
+        if opts.durable {
+            let parent = destination.parent().unwrap_or(destination);
+            durable::sync_dir(parent).map_err(Error::copy(source, destination))?;
+        }
+
+        if let Ok((apparent, allocated)) = sparse::sizes(destination) {
+            cx.apparent.fetch_add(apparent, Ordering::Relaxed);
+            cx.allocated.fetch_add(allocated, Ordering::Relaxed);
+        }
 
-            if opts.remove_copied_files {
-                fs::remove_file(&object.absolute_path)?;
+        if let Some(journal) = &cx.journal {
+            journal
+                .record(
+                    &object.relative_path,
+                    source,
+                    destination,
+                    Some(source_digest),
+                )
+                .map_err(Error::journal(opts.destination()))?;
+        }
+
+        if let Some(manifest) = &cx.manifest {
+            manifest.record(relative_destination(cx, object, destination), source_digest);
+        }
+
+        // The contents are in place either way, but the source is kept if its copy is incomplete.
+        check_attributes(opts, destination, unset)?;
+
+        if opts.remove_copied_files {
+            if opts.durable {
+                confirm_on_device(opts, source, destination, Some(source_digest))?;
             }
+            fs::remove_file(source).map_err(Error::remove(source))?;
+        }
+        digest = Some(source_digest);
+    }
+
+    Ok(placed(Action::Copy, &destination, digest))
+}
+
+fn placed(action: Action, path: &Path, digest: Option<Digest>) -> (Action, Option<Placed>) {
+    let placed = Placed {
+        path: path.to_owned(),
+        digest,
+    };
+    (action, Some(placed))
+}
+
+/// Recreates the symlink `object` at `destination` with the same target. Whatever was there
+/// before is dealt with like any other conflict.
+fn copy_link(cx: &Context, object: &Object, destination: &Path) -> Result<Action> {
+    let opts = cx.opts;
+    let source = object.absolute_path.as_path();
+    let target = fs::read_link(source).map_err(Error::copy(source, destination))?;
+
+    let action = if fs::read_link(destination).is_ok_and(|existing| existing == target) {
+        Action::Exists
+    } else {
+        write_link(cx, object, destination, &target)?
+    };
+
+    if opts.remove_copied_files && !opts.dry_run && !matches!(action, Action::Skip) {
+        fs::remove_file(source).map_err(Error::remove(source))?;
+    }
+    Ok(action)
+}
+
+/// Puts a link to `target` at `destination`, first settling any conflict with what is there.
+fn write_link(cx: &Context, object: &Object, destination: &Path, target: &Path) -> Result<Action> {
+    let opts = cx.opts;
+    let source = object.absolute_path.as_path();
+
+    let mut destination = Cow::Borrowed(destination);
+    if fs::symlink_metadata(&destination).is_ok() {
+        let resolution = conflict::resolve_link(opts.on_conflict, source, &destination, target)?;
+        println!(
+            "conflict {}: {}",
+            object.relative_path.display(),
+            resolution.describe(opts.dry_run)
+        );
+
+        match resolution {
+            Resolution::Overwrite => (),
+            Resolution::Keep(_) => return Ok(Action::Skip),
+            Resolution::Rename(path) => destination = Cow::Owned(path),
+            Resolution::Existing(_) => return Ok(Action::Exists),
         }
     }
 
+    if opts.dry_run {
+        return Ok(Action::Link);
+    }
+
+    let destination = destination.as_ref();
+    let temp = temp::path(destination);
+    symlink::create(target, &temp).map_err(Error::copy(source, destination))?;
+    if !fs::read_link(&temp).is_ok_and(|written| written == target) {
+        let _ = fs::remove_file(&temp);
+        return Err(BadCopy::new(source, destination).into());
+    }
+
+    if let Some(backup) = cx
+        .backup
+        .as_ref()
+        .filter(|_| fs::symlink_metadata(destination).is_ok())
+    {
+        let backed_up = backup
+            .back_up(opts.destination(), destination)
+            .map_err(Error::backup(destination))?;
+        println!(
+            "backed up {} to {}",
+            object.relative_path.display(),
+            backed_up.display()
+        );
+    }
+
+    fs::rename(&temp, destination).map_err(Error::copy(&temp, destination))?;
+    if opts.durable {
+        let parent = destination.parent().unwrap_or(destination);
+        durable::sync_dir(parent).map_err(Error::copy(source, destination))?;
+    }
+    Ok(Action::Link)
+}
+
+/// Links `object`, another name for a file whose copy is at `leader`, to that copy. The copy was
+/// verified when it was made, so it is not compared with the source again. Whatever already holds
+/// the name is dealt with like any other conflict.
+fn link_or_copy(
+    cx: &Context,
+    object: &Object,
+    destination: &Path,
+    leader: &Placed,
+) -> Result<Action> {
+    let opts = cx.opts;
+    let source = object.absolute_path.as_path();
+    let target = leader.path.as_path();
+
+    let mut destination = Cow::Borrowed(destination);
+    let mut action = Action::Link;
+    if hardlink::same_file(&destination, target).unwrap_or(false) {
+        action = Action::Exists;
+    } else if fs::symlink_metadata(&destination).is_ok() {
+        let comparison = opts.comparison();
+        let resolution = conflict::resolve(opts.on_conflict, source, &destination, |candidate| {
+            Ok(hardlink::same_file(candidate, target).unwrap_or(false)
+                || comparison.matches(source, candidate)?.is_some())
+        })?;
+        println!(
+            "conflict {}: {}",
+            object.relative_path.display(),
+            resolution.describe(opts.dry_run)
+        );
+
+        match resolution {
+            Resolution::Overwrite => (),
+            Resolution::Keep(_) => return Ok(Action::Skip),
+            Resolution::Rename(path) => destination = Cow::Owned(path),
+            Resolution::Existing(path) => {
+                existing(cx, object, &path, None, leader.digest)?;
+                return Ok(Action::Exists);
+            }
+        }
+    }
+
+    if opts.dry_run {
+        return Ok(action);
+    }
+
+    let destination = destination.as_ref();
+    if matches!(action, Action::Link) {
+        let temp = temp::path(destination);
+        fs::hard_link(target, &temp).map_err(Error::copy(source, destination))?;
+        if !hardlink::same_file(&temp, target).unwrap_or(false) {
+            let _ = fs::remove_file(&temp);
+            return Err(BadCopy::new(source, destination).into());
+        }
+
+        if let Some(backup) = cx
+            .backup
+            .as_ref()
+            .filter(|_| fs::symlink_metadata(destination).is_ok())
+        {
+            let backed_up = backup
+                .back_up(opts.destination(), destination)
+                .map_err(Error::backup(destination))?;
+            println!(
+                "backed up {} to {}",
+                object.relative_path.display(),
+                backed_up.display()
+            );
+        }
+
+        fs::rename(&temp, destination).map_err(Error::copy(&temp, destination))?;
+        if opts.durable {
+            let parent = destination.parent().unwrap_or(destination);
+            durable::sync_dir(parent).map_err(Error::copy(source, destination))?;
+        }
+    }
+
+    if let Some(journal) = &cx.journal {
+        journal
+            .record(&object.relative_path, source, destination, leader.digest)
+            .map_err(Error::journal(opts.destination()))?;
+    }
+
+    if let (Some(manifest), Some(digest)) = (&cx.manifest, leader.digest) {
+        manifest.record(relative_destination(cx, object, destination), digest);
+    }
+
+    if opts.remove_copied_files {
+        fs::remove_file(source).map_err(Error::remove(source))?;
+    }
+    Ok(action)
+}
+
+/// Makes a node like the special file `object` at `destination`. Device nodes usually need
+/// privileges, and without them are skipped with a warning.
+fn recreate_special(
+    cx: &Context,
+    object: &Object,
+    destination: &Path,
+    kind: Kind,
+) -> Result<Action> {
+    let opts = cx.opts;
+    let source = object.absolute_path.as_path();
+    let action = if special::same_node(source, destination).unwrap_or(false) {
+        Action::Exists
+    } else {
+        make_node(cx, object, destination, kind)?
+    };
+
+    if opts.remove_copied_files && !opts.dry_run && !matches!(action, Action::Skip) {
+        fs::remove_file(source).map_err(Error::remove(source))?;
+    }
+    Ok(action)
+}
+
+/// Makes the node at `destination`, first settling any conflict with what is there.
+fn make_node(cx: &Context, object: &Object, destination: &Path, kind: Kind) -> Result<Action> {
+    let opts = cx.opts;
+    let source = object.absolute_path.as_path();
+
+    let mut destination = Cow::Borrowed(destination);
+    if fs::symlink_metadata(&destination).is_ok() {
+        let resolution = conflict::resolve_special(opts.on_conflict, source, &destination)?;
+        println!(
+            "conflict {}: {}",
+            object.relative_path.display(),
+            resolution.describe(opts.dry_run)
+        );
+
+        match resolution {
+            Resolution::Overwrite => (),
+            Resolution::Keep(_) => return Ok(Action::Skip),
+            Resolution::Rename(path) => destination = Cow::Owned(path),
+            Resolution::Existing(_) => return Ok(Action::Exists),
+        }
+    }
+
+    if opts.dry_run {
+        return Ok(Action::Create);
+    }
+
+    let destination = destination.as_ref();
+    let temp = temp::path(destination);
+    match special::recreate(source, &temp) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            eprintln!(
+                "warning: skipped {} {}: {}",
+                kind,
+                object.relative_path.display(),
+                e
+            );
+            return Ok(Action::Skip);
+        }
+        Err(e) => return Err(Error::copy(source, destination)(e)),
+    }
+
+    if let Some(backup) = cx
+        .backup
+        .as_ref()
+        .filter(|_| fs::symlink_metadata(destination).is_ok())
+    {
+        let backed_up = backup
+            .back_up(opts.destination(), destination)
+            .map_err(Error::backup(destination))?;
+        println!(
+            "backed up {} to {}",
+            object.relative_path.display(),
+            backed_up.display()
+        );
+    }
+
+    fs::rename(&temp, destination).map_err(Error::copy(&temp, destination))?;
+    if opts.durable {
+        let parent = destination.parent().unwrap_or(destination);
+        durable::sync_dir(parent).map_err(Error::copy(source, destination))?;
+    }
+    Ok(Action::Create)
+}
+
+/// Copies `object` to `temp` and verifies the bytes written there, on behalf of `destination`.
+fn copy_and_verify(
+    opts: &Opts,
+    object: &Object,
+    destination: &Path,
+    temp: &Path,
+) -> Result<(Digest, Vec<Unset>)> {
+    let source = object.absolute_path.as_path();
+    let preserve = opts.preserve();
+
+    // Taken before the copy, which would otherwise disturb the source's access time.
+    let metadata = if preserve.is_empty() {
+        None
+    } else {
+        Some(fs::metadata(source).map_err(Error::metadata(source))?)
+    };
+
+    let (source_digest, permissions) = object
+        .copy_to(temp, opts)
+        .map_err(Error::copy(source, destination))?;
+    let temp_digest = opts.comparison().digest(temp)?;
+    if source_digest != temp_digest || opts.full && !compare::files_match(source, temp)? {
+        return Err(BadCopy::new(source, destination).into());
+    }
+
+    // Reading the copy back may itself have touched its access time, so attributes come last, and
+    // extended attributes after the owner, whose change would clear file capabilities. The mode
+    // follows them, since a read-only copy would refuse them.
+    let mut unset = Vec::new();
+    if let Some(metadata) = &metadata {
+        unset = preserve
+            .apply_ownership(metadata, temp)
+            .map_err(Error::preserve(destination))?;
+    }
+    let attributes = opts.attributes();
+    unset.extend(
+        attributes
+            .copy(source, temp)
+            .map_err(Error::copy(source, destination))?,
+    );
+    fs::set_permissions(temp, permissions).map_err(Error::copy(source, destination))?;
+    if let Some(metadata) = &metadata {
+        preserve
+            .apply(metadata, temp)
+            .map_err(Error::preserve(destination))?;
+    }
+
+    if opts.durable {
+        File::open(temp)
+            .and_then(|file| file.sync_all())
+            .map_err(Error::preserve(destination))?;
+    }
+    Ok((source_digest, unset))
+}
+
+/// Finishes a file whose destination already holds a copy of it, returning the digest recorded
+/// for it.
+fn existing(
+    cx: &Context,
+    object: &Object,
+    destination: &Path,
+    previous: Option<&journal::Entry>,
+    matched: Option<Digest>,
+) -> Result<Option<Digest>> {
+    let opts = cx.opts;
+    let source = object.absolute_path.as_path();
+
+    // A manifest needs a digest even for files that were already in place, and one made with a
+    // different algorithm on an earlier run won't do.
+    let recorded = previous
+        .and_then(|entry| entry.digest)
+        .filter(|digest| digest.algorithm() == opts.hash());
+    let digest = match (recorded.or(matched), &cx.manifest) {
+        (None, Some(_)) => Some(opts.comparison().digest(destination)?),
+        (digest, _) => digest,
+    };
+
+    if let Some(journal) = &cx.journal {
+        if previous.is_none() || digest != recorded {
+            journal
+                .record(&object.relative_path, source, destination, digest)
+                .map_err(Error::journal(opts.destination()))?;
+        }
+    }
+
+    if let (Some(manifest), Some(digest)) = (&cx.manifest, digest) {
+        manifest.record(relative_destination(cx, object, destination), digest);
+    }
+
+    if opts.remove_copied_files && !opts.dry_run {
+        if opts.durable {
+            confirm_on_device(opts, source, destination, None)?;
+        }
+        fs::remove_file(source).map_err(Error::remove(source))?;
+    }
+    Ok(digest)
+}
+
+/// Reads `destination` back from the device, bypassing the page cache where possible, and checks
+/// it against `source` before the source may be removed.
+fn confirm_on_device(
+    opts: &Opts,
+    source: &Path,
+    destination: &Path,
+    expected: Option<Digest>,
+) -> Result<()> {
+    let expected = match expected {
+        Some(digest) => digest,
+        None => Digest::from_path(source, opts.hash()).map_err(Error::hash_source(source))?,
+    };
+    let mode = opts.uncached().unwrap_or(Uncached::Evict);
+    let actual = durable::digest_uncached(destination, mode, expected.algorithm())
+        .map_err(Error::hash_destination(destination))?;
+    if expected != actual {
+        return Err(BadCopy::new(source, destination).into());
+    }
+    Ok(())
+}
+
+fn create_dir(opts: &Opts, source: &Path, path: &Path) -> Result<()> {
+    fs::create_dir_all(path).map_err(Error::mkdir(path))?;
+    let unset = opts
+        .attributes()
+        .copy(source, path)
+        .map_err(Error::mkdir(path))?;
+    if opts.durable {
+        if let Some(parent) = path.parent() {
+            durable::sync_dir(parent).map_err(Error::mkdir(path))?;
+        }
+    }
+    check_attributes(opts, path, unset)
+}
+
+/// Reports the attributes that could not be set on `destination`, failing in strict mode.
+fn check_attributes(opts: &Opts, destination: &Path, unset: Vec<Unset>) -> Result<()> {
+    for attribute in &unset {
+        eprintln!(
+            "warning: unable to set {} on {}: {}",
+            attribute.name,
+            destination.display(),
+            attribute.error
+        );
+    }
+
+    if opts.strict && !unset.is_empty() {
+        return Err(Error::Attributes {
+            path: destination.to_owned(),
+            unset: unset.len(),
+        });
+    }
     Ok(())
 }
+
+/// The path of `destination` relative to the destination root, which differs from the path of
+/// the source when a conflict caused it to be renamed.
+fn relative_destination<'a>(cx: &Context, object: &'a Object, destination: &'a Path) -> &'a Path {
+    destination
+        .strip_prefix(cx.opts.destination())
+        .unwrap_or(&object.relative_path)
+}
@@ -0,0 +1,245 @@
+//! A record of every file verified during a run, in the BSD-style format written by checksum
+//! tools such as `sha256sum --tag`.
+//!
+//! Lines take the form `<ALGORITHM> (<relative path>) = <digest>` and are sorted by path so that
+//! the manifest for an unchanged tree is identical from one run to the next. As with `sha256sum`,
+//! a path containing a backslash or a newline is escaped and its line is prefixed with a
+//! backslash. Untagged `<digest>  <relative path>` lines, as written by plain `sha256sum`, are
+//! also read and taken to be SHA-256.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::hash::{Algorithm, Digest};
+
+#[derive(Debug, Default)]
+pub struct Manifest {
+    entries: Mutex<Vec<(PathBuf, Digest)>>,
+}
+
+impl Manifest {
+    pub fn record(&self, path: &Path, digest: Digest) {
+        self.entries.lock().unwrap().push((path.to_owned(), digest));
+    }
+
+    pub fn write(self, path: &Path) -> io::Result<()> {
+        let mut entries = self.entries.into_inner().unwrap();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        for (path, digest) in entries {
+            let path = path.to_string_lossy();
+            if path.contains(['\\', '\n']) {
+                let path = path.replace('\\', "\\\\").replace('\n', "\\n");
+                writeln!(
+                    writer,
+                    "\\{} ({}) = {}",
+                    digest.algorithm().tag(),
+                    path,
+                    digest
+                )?;
+            } else {
+                writeln!(
+                    writer,
+                    "{} ({}) = {}",
+                    digest.algorithm().tag(),
+                    path,
+                    digest
+                )?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+/// Reads the entries of a manifest written by `Manifest::write`.
+pub fn read(path: &Path) -> io::Result<Vec<(PathBuf, Digest)>> {
+    let mut entries = Vec::new();
+    for (idx, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+
+        let entry = parse_line(&line).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: malformed manifest entry", path.display(), idx + 1),
+            )
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn parse_line(line: &str) -> Option<(PathBuf, Digest)> {
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(line) => (true, line),
+        None => (false, line),
+    };
+
+    let (path, digest) = match parse_tagged(line) {
+        Some(entry) => entry,
+        None => parse_untagged(line)?,
+    };
+    if path.is_empty() {
+        return None;
+    }
+
+    let path = if escaped {
+        unescape(path)?
+    } else {
+        path.to_owned()
+    };
+    Some((PathBuf::from(path), digest))
+}
+
+/// Parses `<ALGORITHM> (<path>) = <digest>`.
+fn parse_tagged(line: &str) -> Option<(&str, Digest)> {
+    let (tag, rest) = line.split_once(" (")?;
+    let algorithm = Algorithm::from_tag(tag)?;
+    let (path, digest) = rest.rsplit_once(") = ")?;
+    Some((path, Digest::parse(algorithm, digest).ok()?))
+}
+
+/// Parses `<digest>  <path>`, as written by `sha256sum`.
+fn parse_untagged(line: &str) -> Option<(&str, Digest)> {
+    let digest = Digest::parse(Algorithm::Sha256, line.get(..64)?).ok()?;
+
+    // sha256sum marks files read in binary mode with an asterisk in place of the second space.
+    let path = line
+        .get(64..)?
+        .strip_prefix("  ")
+        .or_else(|| line.get(64..)?.strip_prefix(" *"))?;
+    Some((path, digest))
+}
+
+fn unescape(path: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(u) = chars.next() {
+        if u != '\\' {
+            unescaped.push(u);
+            continue;
+        }
+
+        match chars.next()? {
+            '\\' => unescaped.push('\\'),
+            'n' => unescaped.push('\n'),
+            _ => return None,
+        }
+    }
+    Some(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scratch;
+
+    const SHA256: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    fn sha256() -> Digest {
+        Digest::parse(Algorithm::Sha256, SHA256).unwrap()
+    }
+
+    #[test]
+    fn parses_tagged_line() {
+        let line = format!("SHA256 (dir/file.txt) = {}", SHA256);
+        assert_eq!(
+            parse_line(&line),
+            Some((PathBuf::from("dir/file.txt"), sha256()))
+        );
+    }
+
+    #[test]
+    fn parses_tagged_line_with_other_algorithm() {
+        let (path, digest) = parse_line("XXH3 (file) = 0011223344556677").unwrap();
+        assert_eq!(path, PathBuf::from("file"));
+        assert_eq!(digest.algorithm(), Algorithm::Xxh3);
+        assert_eq!(digest.to_string(), "0011223344556677");
+    }
+
+    #[test]
+    fn splits_tagged_line_at_last_separator() {
+        let line = format!("SHA256 (a) = b) = {}", SHA256);
+        assert_eq!(parse_line(&line), Some((PathBuf::from("a) = b"), sha256())));
+    }
+
+    #[test]
+    fn parses_untagged_lines_as_sha256() {
+        let text = format!("{}  file name", SHA256);
+        let binary = format!("{} *file name", SHA256);
+        let expected = Some((PathBuf::from("file name"), sha256()));
+        assert_eq!(parse_line(&text), expected);
+        assert_eq!(parse_line(&binary), expected);
+    }
+
+    #[test]
+    fn parses_escaped_line() {
+        let line = format!("\\SHA256 (back\\\\slash\\nnewline) = {}", SHA256);
+        assert_eq!(
+            parse_line(&line),
+            Some((PathBuf::from("back\\slash\nnewline"), sha256()))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        let lines = [
+            format!("SHA256 () = {}", SHA256),
+            format!("MD5 (file) = {}", SHA256),
+            format!("SHA256 (file) = {}0", SHA256),
+            String::from("SHA256 (file) = 0123"),
+            format!("BLAKE3 (file) {}", SHA256),
+            format!("{} file", SHA256),
+            format!("\\SHA256 (bad\\escape) = {}", SHA256),
+        ];
+        for line in &lines {
+            assert_eq!(parse_line(line), None, "accepted {:?}", line);
+        }
+    }
+
+    #[test]
+    fn unescapes_backslashes_and_newlines() {
+        assert_eq!(unescape("plain").as_deref(), Some("plain"));
+        assert_eq!(unescape("a\\\\b\\nc").as_deref(), Some("a\\b\nc"));
+        assert_eq!(unescape("\\t"), None);
+        assert_eq!(unescape("trailing\\"), None);
+    }
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let paths = [
+            "plain",
+            "sub/dir/file",
+            "back\\slash",
+            "new\nline",
+            "both\\\n",
+        ];
+        let manifest = Manifest::default();
+        let mut expected = Vec::new();
+        for (n, path) in paths.iter().enumerate() {
+            let algorithm = if n % 2 == 0 {
+                Algorithm::Sha256
+            } else {
+                Algorithm::Crc32c
+            };
+            let digest = Digest::from_reader(path.as_bytes(), algorithm).unwrap();
+            manifest.record(Path::new(path), digest);
+            expected.push((PathBuf::from(path), digest));
+        }
+        expected.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let scratch = Scratch::new("manifest");
+        let file = scratch.join("manifest");
+        manifest.write(&file).unwrap();
+        let entries = read(&file);
+
+        assert_eq!(entries.unwrap(), expected);
+    }
+}
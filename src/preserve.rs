@@ -0,0 +1,135 @@
+//! Carrying permissions, timestamps and ownership over from a source to its copy.
+
+use std::{
+    fs::{self, FileTimes, Metadata},
+    io,
+    path::Path,
+    str::FromStr,
+};
+
+use crate::xattr::Unset;
+
+/// An attribute group named on the command line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Attribute {
+    Mode,
+    Timestamps,
+    Ownership,
+    All,
+}
+
+impl FromStr for Attribute {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mode" => Ok(Attribute::Mode),
+            "timestamps" => Ok(Attribute::Timestamps),
+            "ownership" => Ok(Attribute::Ownership),
+            "all" => Ok(Attribute::All),
+            _ => Err(format!("unknown attribute: {}", s)),
+        }
+    }
+}
+
+/// The attributes to copy from each source to its destination.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Preserve {
+    mode: bool,
+    timestamps: bool,
+    ownership: bool,
+}
+
+impl Preserve {
+    pub fn new(attributes: &[Attribute]) -> Self {
+        let mut preserve = Preserve::default();
+        for attribute in attributes {
+            match attribute {
+                Attribute::Mode => preserve.mode = true,
+                Attribute::Timestamps => preserve.timestamps = true,
+                Attribute::Ownership => preserve.ownership = true,
+                Attribute::All => {
+                    preserve.mode = true;
+                    preserve.timestamps = true;
+                    preserve.ownership = true;
+                }
+            }
+        }
+        preserve
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Preserve::default()
+    }
+
+    /// Gives `destination` the owner in `metadata`, read from the source, returning it as unset if
+    /// we lack the privileges. This goes before every other attribute, since changing the owner
+    /// clears the set-user-ID and set-group-ID bits and any file capabilities; the bits are put
+    /// back here, the capabilities by copying extended attributes afterwards.
+    pub fn apply_ownership(
+        &self,
+        metadata: &Metadata,
+        destination: &Path,
+    ) -> io::Result<Vec<Unset>> {
+        if !self.ownership {
+            return Ok(Vec::new());
+        }
+
+        let permissions = fs::metadata(destination)?.permissions();
+        let unset = match set_owner(metadata, destination) {
+            Err(error) if error.kind() == io::ErrorKind::PermissionDenied => vec![Unset {
+                name: String::from("ownership"),
+                error,
+            }],
+            Err(error) => return Err(error),
+            Ok(()) => Vec::new(),
+        };
+        fs::set_permissions(destination, permissions)?;
+        Ok(unset)
+    }
+
+    /// Applies the mode and timestamps in `metadata` to `destination`, after its ownership.
+    /// Timestamps go last, since nothing after them should disturb the destination.
+    pub fn apply(&self, metadata: &Metadata, destination: &Path) -> io::Result<()> {
+        if self.mode {
+            fs::set_permissions(destination, metadata.permissions())?;
+        }
+        if self.timestamps {
+            set_times(metadata, destination)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn set_owner(metadata: &Metadata, path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::{chown, MetadataExt};
+
+    // Only a privileged user may give files away, but the group may still be one of ours. The
+    // owner is left unset either way, so the first refusal is the one reported.
+    match chown(path, Some(metadata.uid()), Some(metadata.gid())) {
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            let _ = chown(path, None, Some(metadata.gid()));
+            Err(e)
+        }
+        result => result,
+    }
+}
+
+#[cfg(not(unix))]
+fn set_owner(_metadata: &Metadata, _path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn set_times(metadata: &Metadata, path: &Path) -> io::Result<()> {
+    let times = FileTimes::new()
+        .set_accessed(metadata.accessed()?)
+        .set_modified(metadata.modified()?);
+
+    #[cfg(unix)]
+    let file = fs::File::open(path)?;
+    #[cfg(not(unix))]
+    let file = fs::OpenOptions::new().write(true).open(path)?;
+
+    file.set_times(times)
+}
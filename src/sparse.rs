@@ -0,0 +1,230 @@
+//! Copying files with holes without filling them in.
+//!
+//! The data regions of a sparse source are found with `SEEK_DATA` and `SEEK_HOLE` and only
+//! those are written, leaving the rest of the destination unallocated. The holes still read as
+//! zeros, so they are hashed as zeros and the digest is that of the file's logical contents,
+//! which a plain read of the copy will reproduce.
+
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::hash::{self, Algorithm, Digest, Hasher};
+
+static ZEROS: [u8; 64 * 1024] = [0; 64 * 1024];
+
+/// A region of a file holding data, as a start and end offset.
+pub type Extent = (u64, u64);
+
+/// Copies the `len` bytes of `source` into `destination`, preserving any holes, and returns a
+/// digest of the contents.
+pub fn copy_file(
+    source: &mut File,
+    destination: &mut File,
+    len: u64,
+    algorithm: Algorithm,
+) -> io::Result<Digest> {
+    match data_extents(source, len)? {
+        Some(extents) => copy(source, destination, &extents, len, algorithm),
+        None => hash::copy(source, destination, algorithm),
+    }
+}
+
+/// Finds the data regions of `file`, which is `len` bytes long, if it has any holes. Files
+/// without holes, and filesystems that can't tell, give `None`. The file is left positioned at
+/// its start.
+pub fn data_extents(mut file: &File, len: u64) -> io::Result<Option<Vec<Extent>>> {
+    if len == 0 {
+        return Ok(None);
+    }
+
+    // Probing moves the offset, which is shared with any clone of the file, so it has to be put
+    // back for whatever reads the file next.
+    let extents = find_extents(file, len);
+    file.seek(SeekFrom::Start(0))?;
+    extents
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn find_extents(file: &File, len: u64) -> io::Result<Option<Vec<Extent>>> {
+    use std::os::unix::io::AsRawFd;
+
+    let fd = file.as_raw_fd();
+    let seek = |offset: u64, whence| {
+        let offset = unsafe { libc::lseek(fd, offset as libc::off_t, whence) };
+        if offset < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(offset as u64)
+    };
+
+    match seek(0, libc::SEEK_HOLE) {
+        Ok(hole) if hole >= len => return Ok(None),
+        Ok(_) => (),
+        // The file shrank to nothing since its length was taken.
+        Err(e) if e.raw_os_error() == Some(libc::ENXIO) => return Ok(None),
+        Err(e) if e.raw_os_error() == Some(libc::EINVAL) => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let mut extents = Vec::new();
+    let mut offset = 0;
+    while offset < len {
+        let start = match seek(offset, libc::SEEK_DATA) {
+            Ok(start) => start,
+            // Nothing but a hole from here to the end.
+            Err(e) if e.raw_os_error() == Some(libc::ENXIO) => break,
+            Err(e) => return Err(e),
+        };
+        let end = seek(start, libc::SEEK_HOLE)?.min(len);
+        if start >= end {
+            break;
+        }
+        extents.push((start, end));
+        offset = end;
+    }
+    Ok(Some(extents))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn find_extents(_file: &File, _len: u64) -> io::Result<Option<Vec<Extent>>> {
+    Ok(None)
+}
+
+/// Copies the `extents` of `source` into `destination`, leaving holes everywhere else, and
+/// returns a digest of the `len` bytes of logical contents.
+pub fn copy(
+    source: &mut File,
+    destination: &mut File,
+    extents: &[Extent],
+    len: u64,
+    algorithm: Algorithm,
+) -> io::Result<Digest> {
+    let mut hasher = algorithm.hasher();
+    let mut offset = 0;
+
+    for &(start, end) in extents {
+        hash_zeros(&mut *hasher, start - offset);
+        source.seek(SeekFrom::Start(start))?;
+        destination.seek(SeekFrom::Start(start))?;
+        hash::copy_with(
+            &mut *hasher,
+            (&mut *source).take(end - start),
+            &mut *destination,
+        )?;
+        offset = end;
+    }
+
+    hash_zeros(&mut *hasher, len - offset);
+    // A trailing hole is made by extending the file rather than by writing to it.
+    destination.set_len(len)?;
+    Ok(hasher.finish())
+}
+
+fn hash_zeros(hasher: &mut dyn Hasher, mut len: u64) {
+    while len > 0 {
+        let chunk = len.min(ZEROS.len() as u64) as usize;
+        hasher.update(&ZEROS[..chunk]);
+        len -= chunk as u64;
+    }
+}
+
+/// The length of the file at `path` and the space it actually takes up, which for a sparse file
+/// is less.
+#[cfg(unix)]
+pub fn sizes(path: &Path) -> io::Result<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    // Block counts are always in units of 512 bytes, whatever the filesystem's block size.
+    let metadata = std::fs::metadata(path)?;
+    Ok((metadata.len(), metadata.blocks() * 512))
+}
+
+#[cfg(not(unix))]
+pub fn sizes(path: &Path) -> io::Result<(u64, u64)> {
+    let len = std::fs::metadata(path)?.len();
+    Ok((len, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{self, OpenOptions},
+        io::Write,
+    };
+
+    use super::*;
+    use crate::testing::Scratch;
+
+    /// Copies `contents`, with a hole of `hole` bytes after them, and checks the copy reads the
+    /// same as the source and has the same digest.
+    fn round_trip(name: &str, contents: &[u8], hole: u64) {
+        let scratch = Scratch::new(&format!("sparse-{}", name));
+        let source_path = scratch.join("source");
+        let destination_path = scratch.join("destination");
+
+        let mut source = File::create(&source_path).unwrap();
+        source.write_all(contents).unwrap();
+        if hole > 0 {
+            source.set_len(contents.len() as u64 + hole).unwrap();
+            source.seek(SeekFrom::End(0)).unwrap();
+            source.write_all(contents).unwrap();
+        }
+        drop(source);
+
+        let mut source = File::open(&source_path).unwrap();
+        let len = source.metadata().unwrap().len();
+        let mut destination = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&destination_path)
+            .unwrap();
+        let digest = copy_file(&mut source, &mut destination, len, Algorithm::Sha256).unwrap();
+        drop(destination);
+
+        let expected = fs::read(&source_path).unwrap();
+        let actual = fs::read(&destination_path).unwrap();
+
+        assert_eq!(actual.len() as u64, len);
+        assert!(actual == expected, "{} copy differs from its source", name);
+        assert_eq!(
+            digest,
+            Digest::from_reader(&expected[..], Algorithm::Sha256).unwrap()
+        );
+    }
+
+    #[test]
+    fn copies_plain_file() {
+        round_trip("plain", b"plain contents", 0);
+    }
+
+    #[test]
+    fn copies_empty_file() {
+        round_trip("empty", b"", 0);
+    }
+
+    #[test]
+    fn copies_file_with_hole() {
+        round_trip("holey", &[0xa5; 8192], 4 * 1024 * 1024);
+    }
+
+    #[test]
+    fn leaves_file_at_start() {
+        let scratch = Scratch::new("sparse-offset");
+        let path = scratch.join("file");
+        let mut file = File::create(&path).unwrap();
+        file.write_all(&[1; 4096]).unwrap();
+        file.set_len(1024 * 1024).unwrap();
+        drop(file);
+
+        let mut file = File::open(&path).unwrap();
+        let len = file.metadata().unwrap().len();
+        data_extents(&file, len).unwrap();
+        let offset = file.stream_position().unwrap();
+        assert_eq!(offset, 0);
+    }
+}
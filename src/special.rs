@@ -0,0 +1,196 @@
+//! FIFOs, sockets and device nodes, which have no contents to copy.
+
+use std::{
+    fmt::{self, Display},
+    fs::FileType,
+    io,
+    path::Path,
+    str::FromStr,
+};
+
+/// What to do with special files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// Make a node of the same kind at the destination, where the system allows it.
+    Recreate,
+    /// Leave them out of the copy, with a warning.
+    Skip,
+    /// Treat them as a failure.
+    Fail,
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "recreate" => Ok(Policy::Recreate),
+            "skip" => Ok(Policy::Skip),
+            "fail" => Ok(Policy::Fail),
+            _ => Err(format!("unknown special file policy: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Fifo,
+    Socket,
+    CharDevice,
+    BlockDevice,
+}
+
+impl Kind {
+    /// The kind of special file `file_type` describes, if it describes one at all.
+    #[cfg(unix)]
+    pub fn of(file_type: FileType) -> Option<Self> {
+        use std::os::unix::fs::FileTypeExt;
+
+        if file_type.is_fifo() {
+            Some(Kind::Fifo)
+        } else if file_type.is_socket() {
+            Some(Kind::Socket)
+        } else if file_type.is_char_device() {
+            Some(Kind::CharDevice)
+        } else if file_type.is_block_device() {
+            Some(Kind::BlockDevice)
+        } else {
+            None
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn of(_file_type: FileType) -> Option<Self> {
+        None
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Fifo => "fifo",
+            Kind::Socket => "socket",
+            Kind::CharDevice => "character device",
+            Kind::BlockDevice => "block device",
+        }
+    }
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The number of special files of each kind met during a run.
+#[derive(Clone, Debug, Default)]
+pub struct Counts {
+    fifos: u64,
+    sockets: u64,
+    char_devices: u64,
+    block_devices: u64,
+}
+
+impl Counts {
+    pub fn add(&mut self, kind: Kind) {
+        match kind {
+            Kind::Fifo => self.fifos += 1,
+            Kind::Socket => self.sockets += 1,
+            Kind::CharDevice => self.char_devices += 1,
+            Kind::BlockDevice => self.block_devices += 1,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.fifos + self.sockets + self.char_devices + self.block_devices
+    }
+}
+
+impl Display for Counts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} fifos, {} sockets, {} character devices, {} block devices",
+            self.fifos, self.sockets, self.char_devices, self.block_devices
+        )
+    }
+}
+
+/// Makes a node at `destination` of the same kind, mode and device number as `source`.
+#[cfg(unix)]
+pub fn recreate(source: &Path, destination: &Path) -> io::Result<()> {
+    use std::{
+        ffi::CString,
+        os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    };
+
+    let metadata = std::fs::symlink_metadata(source)?;
+    let path = CString::new(destination.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    // The mode carries the kind of node along with its permissions.
+    let result = unsafe {
+        libc::mknod(
+            path.as_ptr(),
+            metadata.mode() as libc::mode_t,
+            metadata.rdev() as libc::dev_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn recreate(_source: &Path, _destination: &Path) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Whether `a` and `b` are nodes of the same kind and, for devices, the same device.
+#[cfg(unix)]
+pub fn same_node(a: &Path, b: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    const S_IFMT: u32 = 0o170000;
+    let a = std::fs::symlink_metadata(a)?;
+    let b = std::fs::symlink_metadata(b)?;
+    Ok(a.mode() & S_IFMT == b.mode() & S_IFMT && a.rdev() == b.rdev())
+}
+
+#[cfg(not(unix))]
+pub fn same_node(_a: &Path, _b: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{fs, os::unix::net::UnixListener};
+
+    use super::*;
+    use crate::testing::Scratch;
+
+    #[test]
+    fn recreates_node_of_the_same_kind() {
+        let scratch = Scratch::new("special-recreate");
+        let source = scratch.join("source");
+        let destination = scratch.join("destination");
+        let _listener = UnixListener::bind(&source).unwrap();
+
+        recreate(&source, &destination).unwrap();
+        let file_type = fs::symlink_metadata(&destination).unwrap().file_type();
+        assert_eq!(Kind::of(file_type), Some(Kind::Socket));
+        assert!(same_node(&source, &destination).unwrap());
+    }
+
+    #[test]
+    fn regular_file_is_not_the_same_node() {
+        let scratch = Scratch::new("special-same");
+        let source = scratch.join("source");
+        let file = scratch.join("file");
+        let _listener = UnixListener::bind(&source).unwrap();
+        fs::write(&file, "").unwrap();
+
+        assert_eq!(Kind::of(fs::metadata(&file).unwrap().file_type()), None);
+        assert!(!same_node(&source, &file).unwrap());
+        assert!(recreate(&source, &file).is_err());
+    }
+}
@@ -0,0 +1,51 @@
+//! What to do with symbolic links met during a walk.
+
+use std::{io, path::Path, str::FromStr};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Symlinks {
+    /// Recreate each link at the destination with its original target.
+    Preserve,
+    /// Copy whatever each link points to, as though it were in the link's place.
+    Follow,
+    /// Leave links out of the copy.
+    Skip,
+}
+
+impl FromStr for Symlinks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "preserve" => Ok(Symlinks::Preserve),
+            "follow" => Ok(Symlinks::Follow),
+            "skip" => Ok(Symlinks::Skip),
+            _ => Err(format!("unknown symlink mode: {}", s)),
+        }
+    }
+}
+
+/// Creates a symlink at `path` pointing at `target`.
+#[cfg(unix)]
+pub fn create(target: &Path, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+/// Creates a symlink at `path` pointing at `target`. Windows needs to know whether the target is
+/// a directory, so a link to nothing is taken to be a link to a file.
+#[cfg(windows)]
+pub fn create(target: &Path, path: &Path) -> io::Result<()> {
+    use std::os::windows::fs::{symlink_dir, symlink_file};
+
+    let resolved = path.parent().unwrap_or(path).join(target);
+    if resolved.is_dir() {
+        symlink_dir(target, path)
+    } else {
+        symlink_file(target, path)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn create(_target: &Path, _path: &Path) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
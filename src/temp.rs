@@ -0,0 +1,91 @@
+//! Temporary files. Each file is written and verified under a temporary name beside its
+//! destination and only renamed into place once it is known to be good, so that an interrupted
+//! or failed copy never leaves a damaged file under the real name.
+
+use std::{
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::error::{Error, Result};
+
+const PREFIX: &str = ".checked-copy-";
+const SUFFIX: &str = ".tmp";
+
+/// The temporary path beside `destination`. It is hidden, so walks skip it by default, and named
+/// after a hash of the destination's name rather than the name itself, so that it is no longer
+/// than the longest name the filesystem allows.
+pub fn path(destination: &Path) -> PathBuf {
+    let name = destination.file_name().unwrap_or_default();
+    let hash = xxh3_64(name.as_encoded_bytes());
+    destination.with_file_name(format!("{}{:016x}{}", PREFIX, hash, SUFFIX))
+}
+
+fn is_temp(name: &OsStr) -> bool {
+    let name = name.to_string_lossy();
+    name.len() == PREFIX.len() + 16 + SUFFIX.len()
+        && name.starts_with(PREFIX)
+        && name.ends_with(SUFFIX)
+}
+
+/// Removes temporary files left in the directory `dir` by an interrupted run, returning how many
+/// there were.
+pub fn remove_stale(dir: &Path) -> Result<u64> {
+    let mut removed = 0;
+    for entry in fs::read_dir(dir)
+        .map_err(Error::remove(dir))?
+        .filter_map(|entry| entry.ok())
+    {
+        let is_dir = entry
+            .file_type()
+            .map_or(true, |file_type| file_type.is_dir());
+        if !is_dir && is_temp(&entry.file_name()) {
+            fs::remove_file(entry.path()).map_err(Error::remove(&entry.path()))?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scratch;
+
+    #[test]
+    fn path_is_hidden_beside_destination() {
+        let destination = Path::new("dir").join("x".repeat(255));
+        let temp = path(&destination);
+        assert_eq!(temp.parent(), destination.parent());
+        assert!(is_temp(temp.file_name().unwrap()));
+        assert_ne!(path(Path::new("dir/other")), temp);
+    }
+
+    #[test]
+    fn removes_only_stale_temporary_files() {
+        let scratch = Scratch::new("temp-stale");
+        let stale = path(&scratch.join("copied"));
+        let kept = [
+            scratch.join("copied"),
+            scratch.join(".checked-copy-notes.tmp"),
+            scratch
+                .join("sub")
+                .join(path(Path::new("nested")).file_name().unwrap()),
+        ];
+        fs::create_dir_all(scratch.join("sub")).unwrap();
+        fs::create_dir(path(&scratch.join("dir"))).unwrap();
+        for file in kept.iter().chain([&stale]) {
+            fs::write(file, "").unwrap();
+        }
+
+        assert_eq!(remove_stale(scratch.path()).unwrap(), 1);
+        assert!(!stale.exists());
+        assert!(path(&scratch.join("dir")).is_dir());
+        for file in &kept {
+            assert!(file.exists(), "removed {}", file.display());
+        }
+    }
+}
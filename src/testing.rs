@@ -0,0 +1,35 @@
+//! Helpers shared by the tests.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// A fresh directory under the system's temporary directory, removed again when dropped, so
+/// that a failing test cleans up after itself too.
+pub struct Scratch(PathBuf);
+
+impl Scratch {
+    /// Makes an empty directory for the test `name`, which should be unique across the tests.
+    pub fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("checked-copy-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Scratch(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
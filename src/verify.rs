@@ -0,0 +1,147 @@
+//! Comparison of an existing destination with its source, without copying anything.
+
+use std::{
+    fmt::{self, Display},
+    fs,
+    path::Path,
+};
+
+use crate::{
+    error::{Error, Result},
+    journal::JOURNAL_DIR,
+    special::{self, Kind},
+    walk, VerifyOpts,
+};
+
+#[derive(Clone, Debug, Default)]
+struct Report {
+    matched: u64,
+    missing: u64,
+    extra: u64,
+    mismatched: u64,
+    unreadable: u64,
+}
+
+impl Report {
+    fn differences(&self) -> u64 {
+        self.missing + self.extra + self.mismatched + self.unreadable
+    }
+
+    fn missing(&mut self, path: &Path) {
+        self.missing += 1;
+        println!("missing {}", path.display());
+    }
+
+    fn extra(&mut self, path: &Path) {
+        self.extra += 1;
+        println!("extra {}", path.display());
+    }
+
+    fn mismatched(&mut self, path: &Path) {
+        self.mismatched += 1;
+        println!("mismatched {}", path.display());
+    }
+
+    fn unreadable(&mut self, error: Error) {
+        self.unreadable += 1;
+        println!("unreadable {}", error);
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} matched, {} missing, {} extra, {} mismatched, {} unreadable",
+            self.matched, self.missing, self.extra, self.mismatched, self.unreadable
+        )
+    }
+}
+
+pub fn verify(opts: &VerifyOpts) -> Result<()> {
+    let mut report = Report::default();
+    let filter = opts.filter();
+    let comparison = opts.comparison();
+
+    for entry in walk(opts.source(), &filter, false) {
+        let object = match entry {
+            Ok(object) => object,
+            Err(e) => {
+                report.unreadable(e);
+                continue;
+            }
+        };
+
+        let destination = opts.destination().join(&object.relative_path);
+
+        if object.file_type.is_dir() {
+            if !destination.is_dir() {
+                report.missing(&object.relative_path);
+            }
+            continue;
+        }
+
+        if object.file_type.is_file() {
+            if !destination.is_file() {
+                report.missing(&object.relative_path);
+                continue;
+            }
+            match comparison.matches(&object.absolute_path, &destination) {
+                Ok(Some(_)) => report.matched += 1,
+                Ok(None) => report.mismatched(&object.relative_path),
+                Err(e) => report.unreadable(e),
+            }
+            continue;
+        }
+
+        // Links are compared by where they point, and special files by their kind and device.
+        let same = if object.file_type.is_symlink() {
+            let source = &object.absolute_path;
+            match fs::read_link(source) {
+                Ok(target) => fs::read_link(&destination).map(|existing| existing == target),
+                Err(e) => {
+                    report.unreadable(Error::metadata(source)(e));
+                    continue;
+                }
+            }
+        } else if Kind::of(object.file_type).is_some() {
+            special::same_node(&object.absolute_path, &destination)
+        } else {
+            continue;
+        };
+        match same {
+            Ok(true) => report.matched += 1,
+            Ok(false) => report.mismatched(&object.relative_path),
+            Err(_) if fs::symlink_metadata(&destination).is_err() => {
+                report.missing(&object.relative_path)
+            }
+            // Something other than a link stands in for one.
+            Err(_) => report.mismatched(&object.relative_path),
+        }
+    }
+
+    for entry in walk(opts.destination(), &filter, false) {
+        let object = match entry {
+            Ok(object) => object,
+            Err(e) => {
+                report.unreadable(e);
+                continue;
+            }
+        };
+
+        if object.relative_path.starts_with(JOURNAL_DIR) {
+            continue;
+        }
+
+        if fs::symlink_metadata(opts.source().join(&object.relative_path)).is_err() {
+            report.extra(&object.relative_path);
+        }
+    }
+
+    println!("{}", report);
+
+    match report.differences() {
+        0 => Ok(()),
+        n => Err(Error::Differences(n)),
+    }
+}
@@ -0,0 +1,164 @@
+//! Copying extended attributes. On Linux these also carry POSIX ACLs, as
+//! `system.posix_acl_access` and `system.posix_acl_default`, and SELinux labels, as
+//! `security.selinux`, so a single mechanism covers all three.
+
+use std::{io, path::Path};
+
+const ACL_ATTRIBUTES: &[&[u8]] = &[b"system.posix_acl_access", b"system.posix_acl_default"];
+
+/// Which extended attributes to copy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Attributes {
+    /// Every readable attribute other than ACLs.
+    xattrs: bool,
+    acls: bool,
+}
+
+/// An attribute that could not be set on a copy.
+#[derive(Debug)]
+pub struct Unset {
+    pub name: String,
+    pub error: io::Error,
+}
+
+impl Attributes {
+    pub fn new(xattrs: bool, acls: bool) -> Self {
+        Attributes { xattrs, acls }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.xattrs && !self.acls
+    }
+
+    fn wants(&self, name: &[u8]) -> bool {
+        if ACL_ATTRIBUTES.contains(&name) {
+            self.acls
+        } else {
+            self.xattrs
+        }
+    }
+
+    /// Copies the selected attributes of `source` to `destination`, returning those that could
+    /// not be set. Attributes the source will not let us read are left alone, as are all of them
+    /// on filesystems without extended attributes.
+    pub fn copy(&self, source: &Path, destination: &Path) -> io::Result<Vec<Unset>> {
+        if self.is_empty() {
+            return Ok(Vec::new());
+        }
+        copy(self, source, destination)
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn copy(attributes: &Attributes, source: &Path, destination: &Path) -> io::Result<Vec<Unset>> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let c_path = |path: &Path| {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    };
+    let source = c_path(source)?;
+    let destination = c_path(destination)?;
+
+    let names = match sys::list(&source) {
+        Ok(names) => names,
+        Err(e) if is_unsupported(&e) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut unset = Vec::new();
+    for name in names {
+        if !attributes.wants(name.as_bytes()) {
+            continue;
+        }
+
+        let value = match sys::get(&source, &name) {
+            Ok(value) => value,
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied || is_unsupported(&e) => continue,
+            Err(e) => return Err(e),
+        };
+
+        if let Err(error) = sys::set(&destination, &name, &value) {
+            unset.push(Unset {
+                name: name.to_string_lossy().into_owned(),
+                error,
+            });
+        }
+    }
+    Ok(unset)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn copy(_attributes: &Attributes, _source: &Path, _destination: &Path) -> io::Result<Vec<Unset>> {
+    static XATTRS_UNSUPPORTED: std::sync::Once = std::sync::Once::new();
+    XATTRS_UNSUPPORTED.call_once(|| {
+        eprintln!("note: extended attributes and ACLs are not copied on this platform");
+    });
+    Ok(Vec::new())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn is_unsupported(e: &io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(libc::ENOTSUP) | Some(libc::ENODATA))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod sys {
+    use std::{
+        ffi::{CStr, CString},
+        io, ptr,
+    };
+
+    /// Lists the names of the attributes of `path`, without following a final symlink.
+    pub fn list(path: &CStr) -> io::Result<Vec<CString>> {
+        let buf = read(|buf, len| unsafe { libc::llistxattr(path.as_ptr(), buf.cast(), len) })?;
+        Ok(buf
+            .split(|&byte| byte == 0)
+            .filter(|name| !name.is_empty())
+            .filter_map(|name| CString::new(name).ok())
+            .collect())
+    }
+
+    pub fn get(path: &CStr, name: &CStr) -> io::Result<Vec<u8>> {
+        read(|buf, len| unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), buf.cast(), len) })
+    }
+
+    pub fn set(path: &CStr, name: &CStr, value: &[u8]) -> io::Result<()> {
+        let result = unsafe {
+            libc::lsetxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Asks for the size of a value, then reads it, starting over if it grew in between.
+    fn read(mut call: impl FnMut(*mut u8, usize) -> libc::ssize_t) -> io::Result<Vec<u8>> {
+        loop {
+            let len = call(ptr::null_mut(), 0);
+            if len < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut buf = vec![0; len as usize];
+            let len = call(buf.as_mut_ptr(), buf.len());
+            if len < 0 {
+                let e = io::Error::last_os_error();
+                if e.raw_os_error() == Some(libc::ERANGE) {
+                    continue;
+                }
+                return Err(e);
+            }
+
+            buf.truncate(len as usize);
+            return Ok(buf);
+        }
+    }
+}